@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::output::{self, CacheDirStats, CacheKind, Message};
+use crate::runner::Runner;
+use crate::utils::{format_size, path_to_key, walk_files};
+
+const CACHE_USAGE_FILE_NAME: &str = "wallpapers-cache-usage.toml";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheUsage {
+    /// Rescaled wallpapers removed to respect `max_cache_size`. These are not
+    /// generated again by generate-cache, only when played.
+    #[serde(default)]
+    evicted: BTreeSet<String>,
+    // Tables must come after plain values when serializing to toml
+    /// When each file was last loaded into mpv, in seconds since the unix epoch.
+    #[serde(default)]
+    loaded: BTreeMap<String, u64>,
+}
+
+impl CacheUsage {
+    fn path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join(CACHE_USAGE_FILE_NAME)
+    }
+
+    /// Reads the cache usage. A missing or corrupted file is treated as empty, since
+    /// it only affects which rescaled copies get evicted first.
+    pub fn read(cache_dir: &Path) -> CacheUsage {
+        read_to_string(Self::path(cache_dir))
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, cache_dir: &Path) -> Result<(), String> {
+        let contents =
+            toml::to_string(self).map_err(|e| format!("Failed to serialize cache usage: {}", e))?;
+        write(Self::path(cache_dir), contents)
+            .map_err(|e| format!("Failed to write cache usage: {}", e))
+    }
+
+    pub fn record(&mut self, path: &Path) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let key = path_to_key(path);
+        self.evicted.remove(&key);
+        self.loaded.insert(key, now);
+    }
+
+    pub fn last_loaded(&self, path: &Path) -> Option<u64> {
+        self.loaded.get(&path_to_key(path)).copied()
+    }
+
+    pub fn is_evicted(&self, path: &Path) -> bool {
+        self.evicted.contains(&path_to_key(path))
+    }
+}
+
+struct CachedFile {
+    path: PathBuf,
+    size: u64,
+    /// When the file was last loaded or, if it never was, when it was generated.
+    last_used: u64,
+}
+
+fn list_cached_files(dir: &Path, usage: &CacheUsage) -> Vec<CachedFile> {
+    let Ok(files) = walk_files(dir) else {
+        return vec![];
+    };
+
+    files
+        .into_iter()
+        .filter_map(|path| {
+            let metadata = path.metadata().ok()?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            Some(CachedFile {
+                last_used: usage.last_loaded(&path).unwrap_or(modified),
+                size: metadata.len(),
+                path,
+            })
+        })
+        .collect()
+}
+
+fn total_size(files: &[CachedFile]) -> u64 {
+    files.iter().map(|file| file.size).sum()
+}
+
+/// The size of the files that are never evicted: thumbnails and palettes.
+fn kept_size(config: &Config, usage: &CacheUsage) -> u64 {
+    total_size(&list_cached_files(&config.thumbnails_cache_dir, usage))
+        + total_size(&list_cached_files(&config.palettes_cache_dir, usage))
+}
+
+pub fn print_stats(config: &Config) {
+    let usage = CacheUsage::read(&config.cache_dir);
+    let directories: Vec<CacheDirStats> = [
+        (CacheKind::Thumbnail, &config.thumbnails_cache_dir),
+        (CacheKind::Rescaled, &config.wallpapers_rescaled_dir),
+        (CacheKind::Palette, &config.palettes_cache_dir),
+    ]
+    .into_iter()
+    .map(|(kind, dir)| {
+        let files = list_cached_files(dir, &usage);
+        CacheDirStats {
+            kind,
+            path: dir.clone(),
+            files: files.len(),
+            size: total_size(&files),
+        }
+    })
+    .collect();
+    let total = directories.iter().map(|stats| stats.size).sum();
+    let human = || {
+        for (name, stats) in ["Thumbnails", "Rescaled wallpapers", "Palettes"]
+            .iter()
+            .zip(&directories)
+        {
+            println!(
+                "{}: {} files, {} ({})",
+                name,
+                stats.files,
+                format_size(stats.size),
+                stats.path.to_string_lossy()
+            );
+        }
+        if !usage.evicted.is_empty() {
+            println!(
+                "{} rescaled wallpapers were evicted, and will be generated again when played",
+                usage.evicted.len()
+            );
+        }
+        match config.max_cache_size {
+            Some(max) => println!(
+                "Total: {} of {} allowed",
+                format_size(total),
+                format_size(max)
+            ),
+            None => println!("Total: {} (no max_cache_size set)", format_size(total)),
+        }
+    };
+    if output::is_json() {
+        output::event(Message::CacheStats {
+            directories,
+            evicted: usage.evicted.len(),
+            total,
+            max: config.max_cache_size,
+        });
+    } else {
+        human();
+    }
+}
+
+/// Removes the least recently used rescaled wallpapers until the cache fits in
+/// `max_cache_size`. Thumbnails are never evicted, since they're needed for
+/// selection, and neither are palettes, which are small. Both still count
+/// towards the size. Evicted wallpapers are rescaled again when played.
+pub fn prune(config: &Config, runner: &Runner) -> Result<(), String> {
+    let mut usage = CacheUsage::read(&config.cache_dir);
+    let mut rescaled = list_cached_files(&config.wallpapers_rescaled_dir, &usage);
+
+    let mut total = kept_size(config, &usage) + total_size(&rescaled);
+    let Some(max) = config.max_cache_size else {
+        info!("No max_cache_size set. Nothing to prune.");
+        output::event(Message::CachePruned { total, max: None });
+        return Ok(());
+    };
+    if total <= max {
+        info!(
+            "Cache uses {} of {}. Nothing to prune.",
+            format_size(total),
+            format_size(max)
+        );
+        output::event(Message::CachePruned {
+            total,
+            max: Some(max),
+        });
+        return Ok(());
+    }
+
+    rescaled.sort_by_key(|file| file.last_used);
+    for file in rescaled {
+        if total <= max {
+            break;
+        }
+        info!(
+            "{} {} ({})",
+            if runner.is_dry_run() {
+                "Would evict"
+            } else {
+                "Evicting"
+            },
+            file.path.to_string_lossy(),
+            format_size(file.size)
+        );
+        runner
+            .remove_file(&file.path)
+            .map_err(|e| format!("Failed to remove {}: {}", file.path.to_string_lossy(), e))?;
+        usage.evicted.insert(path_to_key(&file.path));
+        total -= file.size;
+        output::event(Message::Evicted {
+            path: file.path.clone(),
+            size: file.size,
+            dry_run: runner.is_dry_run(),
+        });
+    }
+
+    if !runner.is_dry_run() {
+        usage.write(&config.cache_dir)?;
+    }
+
+    if total > max {
+        warn!(
+            "Cache still uses {} after evicting every rescaled wallpaper.",
+            format_size(total)
+        );
+    }
+    output::event(Message::CachePruned {
+        total,
+        max: Some(max),
+    });
+
+    Ok(())
+}
+
+/// Points out when the cache has grown past `max_cache_size`.
+pub fn warn_if_over_budget(config: &Config) {
+    let Some(max) = config.max_cache_size else {
+        return;
+    };
+    let usage = CacheUsage::read(&config.cache_dir);
+    let total = kept_size(config, &usage)
+        + total_size(&list_cached_files(&config.wallpapers_rescaled_dir, &usage));
+    if total > max {
+        warn!(
+            "Cache uses {}, more than max_cache_size ({}). Run `cache prune` to evict old rescaled wallpapers.",
+            format_size(total),
+            format_size(max)
+        );
+    }
+}
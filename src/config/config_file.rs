@@ -1,33 +1,129 @@
 use std::{
+    collections::BTreeMap,
     env,
-    fs::{create_dir_all, read_to_string, write},
+    fs::{copy, create_dir_all, read_to_string, write},
     io::ErrorKind,
     path::PathBuf,
 };
 
+use log::warn;
 use serde_derive::{Deserialize, Serialize};
 
-use super::full_config::ConfigResolution;
+use super::full_config::{ConfigResolution, RendererKind};
+use super::migration::{migrate, set_version_line, MigrationError, CURRENT_CONFIG_VERSION};
+use super::programs::StaticSetter;
+use super::schedule::{ConfigScheduleEntry, Location};
+use super::validation::{split_toml_error, ConfigProblem, ConfigProblems, KeyLocator};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ConfigFile {
+    /// The layout version. See `migration::CURRENT_CONFIG_VERSION`.
+    pub version: Option<u32>,
+
     pub wallpapers_dir: Option<PathBuf>,
     pub cache_dir: Option<PathBuf>,
     pub socket_path: Option<PathBuf>,
+    /// Where the daemon listens for commands
+    pub control_socket_path: Option<PathBuf>,
     pub resolution: Option<ConfigResolution>,
+    pub max_cache_size: Option<CacheSize>,
+
+    pub ffmpeg_path: Option<PathBuf>,
+    pub ffmpeg_extra_args: Option<Vec<String>>,
+    pub ffprobe_path: Option<PathBuf>,
+    pub ffprobe_extra_args: Option<Vec<String>>,
+    pub sxiv_path: Option<PathBuf>,
+    pub sxiv_extra_args: Option<Vec<String>>,
+    pub mpv_path: Option<PathBuf>,
+    pub mpv_extra_args: Option<Vec<String>>,
+    pub xwinwrap_path: Option<PathBuf>,
+    /// Replaces the default xwinwrap flags
+    pub xwinwrap_args: Option<Vec<String>>,
+    pub mpvpaper_path: Option<PathBuf>,
+    pub mpvpaper_extra_args: Option<Vec<String>>,
+    pub swww_path: Option<PathBuf>,
+    pub swww_extra_args: Option<Vec<String>>,
+    pub swww_daemon_path: Option<PathBuf>,
+    /// `x11` or `wayland`. Detected from the environment if not set.
+    pub renderer: Option<RendererKind>,
+    /// `feh`, `xwallpaper` or `hsetroot`. Sets still images on X11.
+    pub static_setter: Option<StaticSetter>,
+    pub static_setter_path: Option<PathBuf>,
+    /// Replaces the default flags of the static setter
+    pub static_setter_args: Option<Vec<String>>,
+
+    /// Extensions of files that are wallpapers, besides the ones of
+    /// `allowed_mime_types`
+    pub allowed_extensions: Option<Vec<String>>,
+    /// Like `"video/*"` or `"image/png"`. Files of other types are skipped.
+    pub allowed_mime_types: Option<Vec<String>>,
+
+    /// Named lists of wallpapers, relative to the wallpapers directory
+    pub tags: Option<BTreeMap<String, Vec<PathBuf>>>,
+    /// What to play at each time of day, in the daemon
+    pub schedule: Option<Vec<ConfigScheduleEntry>>,
+    /// Needed for schedule entries that follow the sun
+    pub location: Option<Location>,
+
+    /// Extract the colour palette of every wallpaper when generating the cache
+    pub generate_palettes: Option<bool>,
+
+    /// Shell commands run whenever the wallpaper changes
+    pub on_change: Option<Vec<String>>,
+    /// In seconds. Hooks still running after it are killed.
+    pub hook_timeout: Option<u64>,
+
+    /// How many of the wallpapers shown last are remembered
+    pub history_size: Option<usize>,
+    /// How many of the wallpapers shown last `random` avoids
+    pub random_avoid_recent: Option<usize>,
+    /// Where ratings and favourites are kept
+    pub ratings_path: Option<PathBuf>,
+
+    /// Where the daemon also writes its log
+    pub log_file: Option<PathBuf>,
+    /// The log file is rotated beyond this size. Either bytes, or a size like "10MiB"
+    pub log_max_size: Option<CacheSize>,
+
+    /// The profile to use, out of `profiles`
+    pub profile: Option<String>,
+    /// Named sets of keys, each overriding the rest of the file when selected
+    pub profiles: Option<BTreeMap<String, ConfigFile>>,
+}
+
+/// A size either in bytes, or as a string with a unit, like `"20GiB"`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    untagged,
+    expecting = "expected a number of bytes, or a size like \"20GiB\""
+)]
+pub enum CacheSize {
+    Bytes(u64),
+    WithUnit(String),
 }
 
 const ENV_VAR_XDG_CONFIG_DIR: &str = "XDG_CONFIG_HOME";
 const ENV_VAR_HOME: &str = "HOME";
 
+/// Every configuration key can be set through an environment variable made of
+/// this prefix and the key in upper case, like `WALLPAPER_MANAGER_WALLPAPERS_DIR`.
+pub const ENV_VAR_PREFIX: &str = "WALLPAPER_MANAGER_";
+/// Where the configuration file is. Not a configuration key itself.
+pub const ENV_VAR_CONFIG: &str = "WALLPAPER_MANAGER_CONFIG";
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigFileReadError {
     #[error("No configuration file found at {0}")]
     ConfigFileNotFound(PathBuf),
     #[error("Could not open configuration file at {0}. Permission Denied")]
     ConfigFilePermissionDenied(PathBuf),
-    #[error("Failed to parse configuration file. Error: {0}")]
-    ParsingError(toml::de::Error),
+    #[error("{0}")]
+    Invalid(ConfigProblems),
+    /// Some keys have invalid values. The configuration holds the other ones.
+    #[error("{1}")]
+    PartlyInvalid(Box<ConfigFile>, ConfigProblems),
+    #[error("{0}")]
+    MigrationError(MigrationError),
     #[error("{0}")]
     IoError(std::io::Error),
 }
@@ -43,6 +139,8 @@ pub enum ConfigFileWriteError {
 
     #[error("Permission denied when writing configuration at {0}: {1}")]
     FileWriteFileError(PathBuf, std::io::Error),
+    #[error("Failed to serialize configuration: {0}")]
+    SerializeError(toml::ser::Error),
 }
 impl ConfigFile {
     pub fn write(&self, path: &PathBuf) -> Result<(), ConfigFileWriteError> {
@@ -55,7 +153,12 @@ impl ConfigFile {
             })?;
         }
 
-        write(path, toml::to_string(&self).unwrap()).map_err(|e| match e.kind() {
+        // Going through a toml::Value puts plain values before tables, as toml requires
+        let contents = toml::Value::try_from(self)
+            .and_then(|value| toml::to_string(&value))
+            .map_err(ConfigFileWriteError::SerializeError)?;
+
+        write(path, contents).map_err(|e| match e.kind() {
             ErrorKind::PermissionDenied => {
                 ConfigFileWriteError::FileWriteFileError(path.to_path_buf(), e)
             }
@@ -75,10 +178,246 @@ impl ConfigFile {
             _ => ConfigFileReadError::IoError(e),
         })?;
 
-        toml::from_str(&config_contents).map_err(ConfigFileReadError::ParsingError)
+        let locator = KeyLocator::new(path, &config_contents);
+        let mut table: toml::value::Table = toml::from_str(&config_contents).map_err(|e| {
+            let (message, key) = split_toml_error(&e);
+            ConfigFileReadError::Invalid(ConfigProblems(vec![ConfigProblem {
+                origin: e
+                    .line_col()
+                    .map(|(line, column)| locator.format_position(line, column)),
+                key,
+                message,
+            }]))
+        })?;
+        let migrated = migrate(&mut table).map_err(ConfigFileReadError::MigrationError)?;
+        // Upgrades that only add the version key read the same either way
+        if migrated.changed_keys {
+            warn!(
+                "Configuration file {} uses the layout of version {}. It was upgraded in memory. Run `wallpaper-manager migrate-config` to upgrade the file.",
+                path.to_string_lossy(),
+                migrated.original_version
+            );
+        }
+
+        let (config_file, unknown_keys, problems) = match Self::from_table(table.clone()) {
+            Ok((config_file, unknown_keys)) => (config_file, unknown_keys, vec![]),
+            Err(e) => Self::from_valid_keys(table, &locator, e),
+        };
+        for key in unknown_keys {
+            warn!(
+                "Unknown configuration key \"{}\" in {}. Ignoring it.",
+                key,
+                path.to_string_lossy()
+            );
+        }
+        if !problems.is_empty() {
+            return Err(ConfigFileReadError::PartlyInvalid(
+                Box::new(config_file),
+                ConfigProblems(problems),
+            ));
+        }
+        Ok(config_file)
+    }
+
+    /// Deserializes a parsed configuration, also returning the keys that are not
+    /// part of it, so typos don't go unnoticed.
+    fn from_table(table: toml::value::Table) -> Result<(ConfigFile, Vec<String>), toml::de::Error> {
+        let mut unknown_keys = vec![];
+        let config_file = serde_ignored::deserialize(toml::Value::Table(table), |path| {
+            unknown_keys.push(path.to_string())
+        })?;
+        Ok((config_file, unknown_keys))
+    }
+
+    /// Deserialization stops at the first error, so each key is tried on its own
+    /// to report every invalid value at once. Returns the configuration of the
+    /// valid keys, the unknown ones, and the problems with the others.
+    fn from_valid_keys(
+        table: toml::value::Table,
+        locator: &KeyLocator,
+        first_error: toml::de::Error,
+    ) -> (ConfigFile, Vec<String>, Vec<ConfigProblem>) {
+        let mut config_file = ConfigFile::default();
+        let mut unknown_keys = vec![];
+        let mut problems = vec![];
+        for (key, value) in table {
+            let mut single_key_table = toml::value::Table::new();
+            single_key_table.insert(key.clone(), value);
+            match Self::from_table(single_key_table) {
+                Ok((single_key_config, single_key_unknown)) => {
+                    config_file = config_file.layer(single_key_config);
+                    unknown_keys.extend(single_key_unknown);
+                }
+                Err(e) => {
+                    let (message, error_key) = split_toml_error(&e);
+                    let key = error_key.unwrap_or(key);
+                    problems.push(ConfigProblem {
+                        origin: locator.locate(&key),
+                        key: Some(key),
+                        message,
+                    });
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            let (message, key) = split_toml_error(&first_error);
+            problems.push(ConfigProblem {
+                origin: key.as_deref().and_then(|key| locator.locate(key)),
+                key,
+                message,
+            });
+        }
+        (config_file, unknown_keys, problems)
+    }
+
+    /// Rewrites the file in the current layout, keeping the original next to it
+    /// with a `.bak` extension. Returns the backup's path, or `None` if the file
+    /// was already up to date.
+    ///
+    /// When only the version changes, its line is set in place. Otherwise the
+    /// file is written out anew, without its comments, and with its keys sorted.
+    pub fn upgrade_file(path: &PathBuf) -> anyhow::Result<Option<PathBuf>> {
+        let config_contents = read_to_string(path)?;
+        let mut table: toml::value::Table = toml::from_str(&config_contents)?;
+        let migrated = migrate(&mut table)?;
+        if migrated.original_version == CURRENT_CONFIG_VERSION {
+            return Ok(None);
+        }
+
+        let mut backup_path = path.clone().into_os_string();
+        backup_path.push(".bak");
+        let backup_path = PathBuf::from(backup_path);
+        copy(path, &backup_path)?;
+        if migrated.changed_keys {
+            write(path, toml::to_string(&toml::Value::Table(table))?)?;
+            warn!(
+                "{} was rewritten without its comments, which are still in {}",
+                path.to_string_lossy(),
+                backup_path.to_string_lossy()
+            );
+        } else {
+            write(path, set_version_line(&config_contents))?;
+        }
+
+        Ok(Some(backup_path))
+    }
+
+    /// Reads the configuration keys set through `WALLPAPER_MANAGER_*` environment
+    /// variables. Values are read as plain strings, except for `true`/`false`,
+    /// numbers, and TOML arrays or inline tables. Keys ending in `_args` also accept
+    /// a whitespace separated list, and `on_change` a single command.
+    pub fn from_env() -> Result<ConfigFile, ConfigFileReadError> {
+        let mut config_file = ConfigFile::default();
+        let mut problems = vec![];
+
+        for (name, value) in env::vars_os() {
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let Some(key) = name.strip_prefix(ENV_VAR_PREFIX) else {
+                continue;
+            };
+            if name == ENV_VAR_CONFIG {
+                continue;
+            }
+            let origin = Some(format!("environment variable {}", name));
+            let Ok(value) = value.into_string() else {
+                problems.push(ConfigProblem {
+                    origin,
+                    key: None,
+                    message: "not valid UTF-8".to_string(),
+                });
+                continue;
+            };
+
+            let key = key.to_ascii_lowercase();
+            let value = env_var_to_toml(&key, &value);
+            let mut table = toml::value::Table::new();
+            table.insert(key, value);
+            let (single_key_config, unknown_keys) = match Self::from_table(table) {
+                Ok(result) => result,
+                Err(e) => {
+                    let (message, key) = split_toml_error(&e);
+                    problems.push(ConfigProblem {
+                        origin,
+                        key,
+                        message,
+                    });
+                    continue;
+                }
+            };
+            if !unknown_keys.is_empty() {
+                warn!(
+                    "Environment variable {} does not match any configuration key. Ignoring it.",
+                    name
+                );
+            }
+
+            config_file = config_file.layer(single_key_config);
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigFileReadError::PartlyInvalid(
+                Box::new(config_file),
+                ConfigProblems(problems),
+            ));
+        }
+        Ok(config_file)
+    }
+
+    /// Returns this configuration with every key set in `top` replaced.
+    pub fn layer(self, top: ConfigFile) -> ConfigFile {
+        ConfigFile {
+            version: top.version.or(self.version),
+            wallpapers_dir: top.wallpapers_dir.or(self.wallpapers_dir),
+            cache_dir: top.cache_dir.or(self.cache_dir),
+            socket_path: top.socket_path.or(self.socket_path),
+            control_socket_path: top.control_socket_path.or(self.control_socket_path),
+            resolution: top.resolution.or(self.resolution),
+            max_cache_size: top.max_cache_size.or(self.max_cache_size),
+            ffmpeg_path: top.ffmpeg_path.or(self.ffmpeg_path),
+            ffmpeg_extra_args: top.ffmpeg_extra_args.or(self.ffmpeg_extra_args),
+            ffprobe_path: top.ffprobe_path.or(self.ffprobe_path),
+            ffprobe_extra_args: top.ffprobe_extra_args.or(self.ffprobe_extra_args),
+            sxiv_path: top.sxiv_path.or(self.sxiv_path),
+            sxiv_extra_args: top.sxiv_extra_args.or(self.sxiv_extra_args),
+            mpv_path: top.mpv_path.or(self.mpv_path),
+            mpv_extra_args: top.mpv_extra_args.or(self.mpv_extra_args),
+            xwinwrap_path: top.xwinwrap_path.or(self.xwinwrap_path),
+            xwinwrap_args: top.xwinwrap_args.or(self.xwinwrap_args),
+            mpvpaper_path: top.mpvpaper_path.or(self.mpvpaper_path),
+            mpvpaper_extra_args: top.mpvpaper_extra_args.or(self.mpvpaper_extra_args),
+            swww_path: top.swww_path.or(self.swww_path),
+            swww_extra_args: top.swww_extra_args.or(self.swww_extra_args),
+            swww_daemon_path: top.swww_daemon_path.or(self.swww_daemon_path),
+            renderer: top.renderer.or(self.renderer),
+            static_setter: top.static_setter.or(self.static_setter),
+            static_setter_path: top.static_setter_path.or(self.static_setter_path),
+            static_setter_args: top.static_setter_args.or(self.static_setter_args),
+            allowed_extensions: top.allowed_extensions.or(self.allowed_extensions),
+            allowed_mime_types: top.allowed_mime_types.or(self.allowed_mime_types),
+            tags: top.tags.or(self.tags),
+            schedule: top.schedule.or(self.schedule),
+            location: top.location.or(self.location),
+            generate_palettes: top.generate_palettes.or(self.generate_palettes),
+            on_change: top.on_change.or(self.on_change),
+            hook_timeout: top.hook_timeout.or(self.hook_timeout),
+            history_size: top.history_size.or(self.history_size),
+            random_avoid_recent: top.random_avoid_recent.or(self.random_avoid_recent),
+            ratings_path: top.ratings_path.or(self.ratings_path),
+            log_file: top.log_file.or(self.log_file),
+            log_max_size: top.log_max_size.or(self.log_max_size),
+            profile: top.profile.or(self.profile),
+            profiles: top.profiles.or(self.profiles),
+        }
     }
 
     pub fn resolve_config_path_from_env() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(ENV_VAR_CONFIG) {
+            return Some(PathBuf::from(path));
+        }
+
         let config_dir: PathBuf = match env::var(ENV_VAR_XDG_CONFIG_DIR) {
             Ok(v) => PathBuf::from(v),
             Err(_) => match env::var(ENV_VAR_HOME) {
@@ -90,3 +429,129 @@ impl ConfigFile {
         Some(config_dir.join("wallpaper-manager/config.toml"))
     }
 }
+
+/// How the value of an environment variable is read, after the type of its key.
+enum EnvValueType {
+    /// Taken verbatim, like paths.
+    Text,
+    /// A whitespace separated list, or a TOML array.
+    List,
+    /// A TOML array of shell commands, or else a single command.
+    Commands,
+    Integer,
+    Boolean,
+    /// A number of bytes, or a string like `"20GiB"`.
+    Size,
+    /// A TOML value, like a table, or else a string.
+    Toml,
+}
+
+fn env_value_type(key: &str) -> EnvValueType {
+    match key {
+        "version" | "hook_timeout" | "history_size" | "random_avoid_recent" => {
+            EnvValueType::Integer
+        }
+        "generate_palettes" => EnvValueType::Boolean,
+        "max_cache_size" | "log_max_size" => EnvValueType::Size,
+        "allowed_extensions" | "allowed_mime_types" => EnvValueType::List,
+        "on_change" => EnvValueType::Commands,
+        "resolution" | "tags" | "schedule" | "location" | "profiles" => EnvValueType::Toml,
+        key if key.ends_with("_args") => EnvValueType::List,
+        _ => EnvValueType::Text,
+    }
+}
+
+fn parse_toml_value(value: &str) -> Option<toml::Value> {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()?
+        .remove("value")
+}
+
+/// Reads an environment variable as the type of `key`. Values that don't fit it
+/// are kept as strings, so deserializing reports them.
+fn env_var_to_toml(key: &str, value: &str) -> toml::Value {
+    let trimmed = value.trim();
+    let parsed = match env_value_type(key) {
+        EnvValueType::Text => None,
+        EnvValueType::List if trimmed.starts_with('[') => parse_toml_value(trimmed),
+        EnvValueType::List => Some(toml::Value::Array(
+            value
+                .split_whitespace()
+                .map(|arg| toml::Value::String(arg.to_string()))
+                .collect(),
+        )),
+        EnvValueType::Commands if trimmed.starts_with('[') => parse_toml_value(trimmed),
+        EnvValueType::Commands => Some(toml::Value::Array(vec![toml::Value::String(
+            value.to_string(),
+        )])),
+        EnvValueType::Integer | EnvValueType::Size => {
+            trimmed.parse::<i64>().ok().map(toml::Value::Integer)
+        }
+        EnvValueType::Boolean => trimmed.parse::<bool>().ok().map(toml::Value::Boolean),
+        EnvValueType::Toml => parse_toml_value(trimmed),
+    };
+    parsed.unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> ConfigFile {
+        ConfigFile::from_table(toml::from_str(contents).unwrap())
+            .unwrap()
+            .0
+    }
+
+    /// The layer of the environment variables `WALLPAPER_MANAGER_<KEY>` set to
+    /// each value, without touching this process' environment.
+    fn env_layer(variables: &[(&str, &str)]) -> ConfigFile {
+        let mut table = toml::value::Table::new();
+        for (key, value) in variables {
+            table.insert(key.to_string(), env_var_to_toml(key, value));
+        }
+        ConfigFile::from_table(table).unwrap().0
+    }
+
+    #[test]
+    fn layer_prefers_the_command_line_then_the_environment_then_the_file() {
+        let file = parse(
+            r#"
+            wallpapers_dir = "/file/walls"
+            cache_dir = "/file/cache"
+            socket_path = "/file/socket"
+            hook_timeout = 5
+            "#,
+        );
+        let env = env_layer(&[
+            ("cache_dir", "/env/cache"),
+            ("socket_path", "/env/socket"),
+            ("hook_timeout", "7"),
+        ]);
+        let args = ConfigFile {
+            socket_path: Some(PathBuf::from("/args/socket")),
+            ..ConfigFile::default()
+        };
+
+        let config = file.layer(env).layer(args);
+        assert_eq!(config.wallpapers_dir, Some(PathBuf::from("/file/walls")));
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/env/cache")));
+        assert_eq!(config.socket_path, Some(PathBuf::from("/args/socket")));
+        assert_eq!(config.hook_timeout, Some(7));
+    }
+
+    #[test]
+    fn env_var_to_toml_takes_a_single_on_change_command() {
+        assert_eq!(
+            env_var_to_toml("on_change", "notify-send hi"),
+            toml::Value::Array(vec![toml::Value::String("notify-send hi".to_string())])
+        );
+        assert_eq!(
+            env_var_to_toml("on_change", "[\"a b\", \"c\"]"),
+            toml::Value::Array(vec![
+                toml::Value::String("a b".to_string()),
+                toml::Value::String("c".to_string()),
+            ])
+        );
+    }
+}
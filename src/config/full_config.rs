@@ -1,18 +1,52 @@
-use question::{Answer, Question};
+use log::debug;
 use serde_derive::{Deserialize, Serialize};
-use std::{fs::create_dir_all, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{create_dir_all, read_to_string, remove_file, write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 use thiserror::Error;
 
-use crate::Args;
+use crate::utils::parse_size;
+use crate::{Args, Commands};
 
-use super::config_file::{ConfigFile, ConfigFileWriteError};
+use super::config_file::{CacheSize, ConfigFile, ConfigFileReadError};
+use super::programs::Programs;
+use super::schedule::{Location, ScheduleEntry, ScheduleStart, ScheduleTarget};
+use super::validation::{ConfigProblem, ConfigProblems, KeyLocator, Origins};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "ResolutionValue")]
 pub struct ConfigResolution {
     pub width: i32,
     pub height: i32,
 }
 
+/// A resolution can be written either as a table, or as a string like `"1920x1080"`.
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expected a table with a width and a height, or a string like \"1920x1080\""
+)]
+enum ResolutionValue {
+    Table { width: i32, height: i32 },
+    Text(String),
+}
+
+impl TryFrom<ResolutionValue> for ConfigResolution {
+    type Error = String;
+
+    fn try_from(value: ResolutionValue) -> Result<Self, Self::Error> {
+        match value {
+            ResolutionValue::Table { width, height } => Ok(ConfigResolution { width, height }),
+            ResolutionValue::Text(text) => text.parse(),
+        }
+    }
+}
+
 impl Default for ConfigResolution {
     fn default() -> Self {
         ConfigResolution {
@@ -22,91 +56,503 @@ impl Default for ConfigResolution {
     }
 }
 
+impl FromStr for ConfigResolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('x')
+            .and_then(|(width, height)| {
+                Some(ConfigResolution {
+                    width: width.trim().parse().ok()?,
+                    height: height.trim().parse().ok()?,
+                })
+            })
+            .ok_or_else(|| format!("invalid resolution \"{}\", expected WIDTHxHEIGHT", s))
+    }
+}
+
+/// How wallpapers are drawn on the desktop.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RendererKind {
+    /// xwinwrap and mpv
+    #[default]
+    X11,
+    /// mpvpaper for videos, swww for still images
+    Wayland,
+}
+
+impl RendererKind {
+    /// Wayland if there is a Wayland display, as X11 programs may also run there
+    /// through XWayland but can't draw the wallpaper.
+    fn detect() -> Self {
+        match env::var_os(ENV_VAR_WAYLAND_DISPLAY) {
+            Some(display) if !display.is_empty() => RendererKind::Wayland,
+            _ => RendererKind::X11,
+        }
+    }
+}
+
+const ENV_VAR_WAYLAND_DISPLAY: &str = "WAYLAND_DISPLAY";
 const ENV_VAR_XDG_CONFIG_DIR: &str = "XDG_CONFIG_HOME";
 const ENV_VAR_HOME: &str = "HOME";
 
-#[derive(Serialize, Deserialize, Default)]
+const DEFAULT_SOCKET_PATH: &str = "/tmp/wallpaper-mpv-socket";
+const DEFAULT_CONTROL_SOCKET_PATH: &str = "/tmp/wallpaper-manager-socket";
+const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_HISTORY_SIZE: usize = 100;
+const DEFAULT_RANDOM_AVOID_RECENT: usize = 5;
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_ALLOWED_MIME_TYPES: [&str; 2] = ["video/*", "image/*"];
+/// Where, inside the cache directory, each profile keeps its own cache.
+const PROFILES_CACHE_DIR: &str = "wallpaper-manager-profiles";
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct FullConfig {
-    pub socket_path: Option<PathBuf>,
+    /// mpv's IPC socket, which only the renderers use.
+    pub socket_path: PathBuf,
+    /// Where the daemon listens for commands.
+    pub control_socket_path: PathBuf,
 
     pub cache_dir: PathBuf,
     pub resolution: ConfigResolution,
     pub wallpapers_dir: PathBuf,
     pub thumbnails_cache_dir: PathBuf,
     pub wallpapers_rescaled_dir: PathBuf,
+    pub palettes_cache_dir: PathBuf,
+    /// In bytes. `None` means the cache can grow without limit.
+    pub max_cache_size: Option<u64>,
+    pub programs: Programs,
+    pub renderer: RendererKind,
+    /// Lowercase, without the leading dot.
+    pub allowed_extensions: Vec<String>,
+    pub allowed_mime_types: Vec<String>,
+    /// Wallpapers of each tag, relative to `wallpapers_dir`.
+    pub tags: BTreeMap<String, Vec<PathBuf>>,
+    /// In the order of the configuration. Empty if there is no schedule.
+    pub schedule: Vec<ScheduleEntry>,
+    /// Always set when an entry of `schedule` follows the sun.
+    pub location: Option<Location>,
+    /// Whether `generate-cache` also extracts colour palettes.
+    pub generate_palettes: bool,
+    /// Shell commands run after every wallpaper change.
+    pub on_change: Vec<String>,
+    pub hook_timeout: Duration,
+    /// How many of the wallpapers shown last are remembered.
+    pub history_size: usize,
+    /// How many of the wallpapers shown last `random` avoids.
+    pub random_avoid_recent: usize,
+    pub ratings_path: PathBuf,
+    /// Only the daemon writes to it.
+    pub log_file: Option<PathBuf>,
+    /// In bytes.
+    pub log_max_size: u64,
 }
 
 #[derive(Debug, Error)]
 pub enum ConfigReadError {
     #[error("Could not resolve the config directory. Either provide it as a command line argument (through --config-dir), or set either the {ENV_VAR_XDG_CONFIG_DIR} or {ENV_VAR_HOME} environment variables")]
     NoConfigPath,
-    #[error("Could not resolve the cache directory. Provide it in the configuration file or through --cache-dir")]
-    CacheDirNotFound,
-    #[error("Could not resolve the wallpapers directory. Provide it in the configuration file or through --wallpapers-dir")]
-    WallpaperDirNotProvided,
-    #[error("Failed to create thumbnails cache directory: {0}")]
-    FailedCreateThumbnailDir(std::io::Error),
-    #[error("Failed to create wallpapers rescaled cache directory: {0}")]
-    FailedCreateWallpapersRescaleDir(std::io::Error),
-    #[error("Wallpapers directory does not exist")]
-    WallpaperDirNotFound,
-
+    #[error("No configuration file found at {0}. Run `wallpaper-manager init` to create one.")]
+    ConfigFileNotFound(PathBuf),
     #[error("{0}")]
-    ConfigWriteError(ConfigFileWriteError),
+    ReadError(#[from] ConfigFileReadError),
+    #[error("{0}")]
+    Invalid(ConfigProblems),
 }
 
-pub fn read(args: &Args) -> Result<FullConfig, ConfigReadError> {
-    let config_file_path = args
-        .config_dir
+/// The configuration keys given as command line arguments.
+fn config_from_args(args: &Args) -> ConfigFile {
+    let socket_path = match &args.command {
+        Commands::Daemon { socket_path } | Commands::SelectWallpaper { socket_path, .. } => {
+            socket_path.clone()
+        }
+        _ => None,
+    };
+
+    ConfigFile {
+        wallpapers_dir: args.wallpapers_dir.clone(),
+        cache_dir: args.cache_dir.clone(),
+        socket_path,
+        profile: args.profile.clone(),
+        ..ConfigFile::default()
+    }
+}
+
+pub fn resolve_config_path(args: &Args) -> Result<PathBuf, ConfigReadError> {
+    args.config_dir
         .clone()
         .or_else(ConfigFile::resolve_config_path_from_env)
-        .or_else(|| dirs::config_dir().map(|c| c.join("/")))
-        .ok_or(ConfigReadError::NoConfigPath)?;
+        .or_else(|| dirs::config_dir().map(|c| c.join("wallpaper-manager/config.toml")))
+        .ok_or(ConfigReadError::NoConfigPath)
+}
 
-    if !config_file_path.is_file() {
-        if let Answer::YES = Question::new(&format!("Could not find a configuration file at {}. Would you like to create a default configuration at this location?", config_file_path.to_string_lossy())).confirm() {
-            ConfigFile::default().write(&config_file_path).map_err(ConfigReadError::ConfigWriteError)?;
-            println!("Default configuration file created successfuly")
+/// Checks a directory is writable by creating, then removing, a file in it.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    // Named after the process, so commands running at the same time don't remove
+    // each other's
+    let probe = dir.join(format!(
+        ".wallpaper-manager-write-test-{}",
+        std::process::id()
+    ));
+    write(&probe, b"")?;
+    remove_file(&probe)
+}
+
+/// Reads a configuration layer, keeping its problems to report them along with
+/// those of the other layers. Keys with invalid values are left out of it.
+fn collect_layer(
+    layer: Result<ConfigFile, ConfigFileReadError>,
+    problems: &mut Vec<ConfigProblem>,
+) -> Result<ConfigFile, ConfigReadError> {
+    match layer {
+        Ok(config_file) => Ok(config_file),
+        Err(ConfigFileReadError::Invalid(ConfigProblems(layer_problems))) => {
+            problems.extend(layer_problems);
+            Ok(ConfigFile::default())
+        }
+        Err(ConfigFileReadError::PartlyInvalid(config_file, ConfigProblems(layer_problems))) => {
+            problems.extend(layer_problems);
+            Ok(*config_file)
         }
-        std::process::exit(0)
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn read(args: &Args) -> Result<FullConfig, ConfigReadError> {
+    let config_file_path = resolve_config_path(args)?;
+
+    if !config_file_path.is_file() {
+        return Err(ConfigReadError::ConfigFileNotFound(config_file_path));
     };
 
-    let config_file = ConfigFile::read_from(&config_file_path).unwrap();
-    dbg!(&config_file_path);
-    let cache_dir = args
-        .cache_dir
-        .clone()
-        .or(config_file.cache_dir)
-        .or_else(dirs::cache_dir)
-        .ok_or(ConfigReadError::CacheDirNotFound)?;
+    let mut problems = vec![];
+    let mut file_layer = collect_layer(ConfigFile::read_from(&config_file_path), &mut problems)?;
+    let mut env_layer = collect_layer(ConfigFile::from_env(), &mut problems)?;
+    let args_layer = config_from_args(args);
+    // Keys that are missing because their value was invalid aren't checked any
+    // further, since they are reported already
+    let invalid_keys: Vec<String> = problems
+        .iter()
+        .filter_map(|problem| problem.key.clone())
+        .collect();
+    let is_invalid = |key: &str| {
+        invalid_keys.iter().any(|invalid| {
+            invalid == key
+                || invalid
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    };
 
-    let wallpapers_dir = args
-        .wallpapers_dir
+    let profile_name = args_layer
+        .profile
         .clone()
-        .or(config_file.wallpapers_dir)
-        .ok_or(ConfigReadError::WallpaperDirNotProvided)?;
+        .or_else(|| env_layer.profile.clone())
+        .or_else(|| file_layer.profile.clone());
+    let mut profiles = env_layer
+        .profiles
+        .take()
+        .or_else(|| file_layer.profiles.take())
+        .unwrap_or_default();
+    let available_profiles = profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+    let profile_layer = profile_name.as_ref().and_then(|name| profiles.remove(name));
+
+    let locator = match read_to_string(&config_file_path) {
+        Ok(contents) => KeyLocator::new(&config_file_path, &contents),
+        Err(_) => KeyLocator::empty(&config_file_path),
+    };
+    let origins = Origins::new(
+        locator,
+        profile_name.as_deref().zip(profile_layer.as_ref()),
+        &env_layer,
+        &args_layer,
+    );
+
+    match (&profile_name, &profile_layer) {
+        (Some(name), None) if !is_invalid("profiles") => problems.push(origins.problem(
+            "profile",
+            format!(
+                "there is no profile named \"{}\". Available profiles: {}",
+                name,
+                if available_profiles.is_empty() {
+                    "none"
+                } else {
+                    &available_profiles
+                }
+            ),
+        )),
+        (Some(name), Some(profile))
+            if profile.version.is_some()
+                || profile.profile.is_some()
+                || profile.profiles.is_some() =>
+        {
+            problems.push(origins.problem(
+                "profiles",
+                format!(
+                    "profile \"{}\" can't set version, profile or profiles",
+                    name
+                ),
+            ))
+        }
+        _ => {}
+    }
+    let profile_sets_cache_dir = profile_layer
+        .as_ref()
+        .is_some_and(|profile| profile.cache_dir.is_some());
+
+    // Precedence, from lowest to highest: file, selected profile, environment, command line
+    let config_file = file_layer
+        .layer(profile_layer.unwrap_or_default())
+        .layer(env_layer)
+        .layer(args_layer);
+    debug!(
+        "Read the configuration at {}",
+        config_file_path.to_string_lossy()
+    );
+    let resolution = config_file.resolution.clone().unwrap_or_default();
+    let programs = Programs::from_config_file(&config_file, &resolution);
 
+    if resolution.width <= 0 || resolution.height <= 0 {
+        problems.push(origins.problem(
+            "resolution",
+            format!(
+                "{}x{} is not a valid resolution, both sides must be positive",
+                resolution.width, resolution.height
+            ),
+        ));
+    }
+
+    let wallpapers_dir = config_file.wallpapers_dir.unwrap_or_default();
+    if is_invalid("wallpapers_dir") {
+        // Reported already
+    } else if wallpapers_dir.as_os_str().is_empty() {
+        problems.push(ConfigProblem {
+            origin: None,
+            key: Some("wallpapers_dir".to_string()),
+            message: "not provided. Set it in the configuration file or through --wallpapers-dir"
+                .to_string(),
+        });
+    } else if !wallpapers_dir.is_dir() {
+        problems.push(origins.problem(
+            "wallpapers_dir",
+            format!("{} is not a directory", wallpapers_dir.to_string_lossy()),
+        ));
+    }
+
+    let cache_dir = config_file
+        .cache_dir
+        .or_else(dirs::cache_dir)
+        .unwrap_or_default();
+    // Profiles keep their caches apart, unless they choose a directory themselves
+    let cache_dir = match &profile_name {
+        Some(name) if !cache_dir.as_os_str().is_empty() && !profile_sets_cache_dir => {
+            cache_dir.join(PROFILES_CACHE_DIR).join(name)
+        }
+        _ => cache_dir,
+    };
     let thumbnails_cache_dir = cache_dir.join("wallpapers-thumbnail");
     let wallpapers_rescaled_dir = cache_dir.join("wallpapers-rescaled");
+    let palettes_cache_dir = cache_dir.join("wallpapers-palette");
+    if is_invalid("cache_dir") {
+        // Reported already
+    } else if cache_dir.as_os_str().is_empty() {
+        problems.push(ConfigProblem {
+            origin: None,
+            key: Some("cache_dir".to_string()),
+            message:
+                "could not be resolved. Set it in the configuration file or through --cache-dir"
+                    .to_string(),
+        });
+    } else if let Err(e) = create_dir_all(&thumbnails_cache_dir)
+        .and_then(|_| create_dir_all(&wallpapers_rescaled_dir))
+        .and_then(|_| create_dir_all(&palettes_cache_dir))
+        .and_then(|_| check_writable(&cache_dir))
+    {
+        problems.push(origins.problem(
+            "cache_dir",
+            format!("{} is not writable: {}", cache_dir.to_string_lossy(), e),
+        ));
+    }
 
-    if !thumbnails_cache_dir.is_dir() {
-        create_dir_all(&thumbnails_cache_dir).map_err(ConfigReadError::FailedCreateThumbnailDir)?
+    let with_profile = |default: &str| match &profile_name {
+        Some(name) => PathBuf::from(format!("{}-{}", default, name)),
+        None => PathBuf::from(default),
+    };
+    let socket_path = config_file
+        .socket_path
+        .unwrap_or_else(|| with_profile(DEFAULT_SOCKET_PATH));
+    let control_socket_path = config_file
+        .control_socket_path
+        .unwrap_or_else(|| with_profile(DEFAULT_CONTROL_SOCKET_PATH));
+    for (key, path) in [
+        ("socket_path", &socket_path),
+        ("control_socket_path", &control_socket_path),
+    ] {
+        if let Some(socket_dir) = path.parent() {
+            if !socket_dir.as_os_str().is_empty() && !socket_dir.is_dir() {
+                problems.push(origins.problem(
+                    key,
+                    format!(
+                        "the directory {} does not exist",
+                        socket_dir.to_string_lossy()
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut resolve_size = |key: &str, size: Option<CacheSize>| match size {
+        None => None,
+        Some(CacheSize::Bytes(bytes)) => Some(bytes),
+        Some(CacheSize::WithUnit(size)) => {
+            let bytes = parse_size(&size);
+            if bytes.is_none() {
+                problems.push(origins.problem(
+                    key,
+                    format!(
+                        "invalid size \"{}\". Use a number of bytes, or a size like \"20GiB\"",
+                        size
+                    ),
+                ));
+            }
+            bytes
+        }
+    };
+    let max_cache_size = resolve_size("max_cache_size", config_file.max_cache_size);
+    let log_max_size =
+        resolve_size("log_max_size", config_file.log_max_size).unwrap_or(DEFAULT_LOG_MAX_SIZE);
+
+    let location = config_file.location;
+    if let Some(location) = &location {
+        if !(-90.0..=90.0).contains(&location.latitude) {
+            problems.push(origins.problem(
+                "location.latitude",
+                format!("{} is not between -90 and 90", location.latitude),
+            ));
+        }
+        if !(-180.0..=180.0).contains(&location.longitude) {
+            problems.push(origins.problem(
+                "location.longitude",
+                format!("{} is not between -180 and 180", location.longitude),
+            ));
+        }
     }
-    if !wallpapers_rescaled_dir.is_dir() {
-        create_dir_all(&wallpapers_rescaled_dir)
-            .map_err(ConfigReadError::FailedCreateWallpapersRescaleDir)?
+
+    let allowed_extensions = config_file
+        .allowed_extensions
+        .unwrap_or_default()
+        .iter()
+        .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+        .collect();
+    let allowed_mime_types = config_file.allowed_mime_types.unwrap_or_else(|| {
+        DEFAULT_ALLOWED_MIME_TYPES
+            .iter()
+            .map(|mime_type| mime_type.to_string())
+            .collect()
+    });
+    for (index, mime_type) in allowed_mime_types.iter().enumerate() {
+        if !mime_type.contains('/') {
+            problems.push(origins.problem(
+                &format!("allowed_mime_types.{}", index),
+                format!(
+                    "\"{}\" is not a MIME type, like \"video/mp4\" or \"video/*\"",
+                    mime_type
+                ),
+            ));
+        }
+    }
+
+    let tags = config_file.tags.unwrap_or_default();
+    let mut schedule = vec![];
+    for (index, entry) in config_file.schedule.iter().flatten().enumerate() {
+        let key = |field: &str| format!("schedule.{}.{}", index, field);
+        let entry = match entry.resolve() {
+            Ok(entry) => entry,
+            Err((field, message)) => {
+                let key = field.map_or_else(|| format!("schedule.{}", index), key);
+                problems.push(origins.problem(&key, message));
+                continue;
+            }
+        };
+        match &entry.target {
+            ScheduleTarget::Wallpaper(wallpaper)
+                if !is_invalid("wallpapers_dir") && !wallpapers_dir.join(wallpaper).is_file() =>
+            {
+                problems.push(origins.problem(
+                    &key("wallpaper"),
+                    format!("no wallpaper at {}", wallpaper.to_string_lossy()),
+                ))
+            }
+            ScheduleTarget::Tag(tag) if !is_invalid("tags") && !tags.contains_key(tag) => problems
+                .push(origins.problem(&key("tag"), format!("there is no tag named \"{}\"", tag))),
+            ScheduleTarget::Folder(folder)
+                if !is_invalid("wallpapers_dir") && !wallpapers_dir.join(folder).is_dir() =>
+            {
+                problems.push(origins.problem(
+                    &key("folder"),
+                    format!("no folder at {}", folder.to_string_lossy()),
+                ))
+            }
+            _ => {}
+        }
+        if matches!(entry.start, ScheduleStart::Solar { .. })
+            && location.is_none()
+            && !is_invalid("location")
+        {
+            problems.push(origins.problem(
+                &key("start"),
+                "following the sun needs a location, like location = { latitude = 48.85, longitude = 2.35 }".to_string(),
+            ));
+        }
+        schedule.push(entry);
+    }
+
+    // Ratings are chosen by hand, so they don't belong in the cache unless there
+    // is nowhere else
+    let ratings_path = config_file.ratings_path.unwrap_or_else(|| {
+        dirs::data_dir()
+            .map(|data_dir| data_dir.join("wallpaper-manager/ratings.toml"))
+            .unwrap_or_else(|| cache_dir.join("wallpaper-manager-ratings.toml"))
+    });
+
+    let hook_timeout = config_file
+        .hook_timeout
+        .unwrap_or(DEFAULT_HOOK_TIMEOUT_SECS);
+    if hook_timeout == 0 {
+        problems.push(origins.problem("hook_timeout", "must be at least 1 second".to_string()));
     }
 
-    if !wallpapers_dir.is_dir() {
-        return Err(ConfigReadError::WallpaperDirNotFound);
+    if !problems.is_empty() {
+        return Err(ConfigReadError::Invalid(ConfigProblems(problems)));
     }
 
     Ok(FullConfig {
         cache_dir,
-        socket_path: config_file.socket_path,
-        resolution: config_file.resolution.unwrap_or_default(),
+        socket_path,
+        control_socket_path,
+        resolution,
         wallpapers_dir,
         thumbnails_cache_dir,
         wallpapers_rescaled_dir,
+        palettes_cache_dir,
+        max_cache_size,
+        programs,
+        renderer: config_file.renderer.unwrap_or_else(RendererKind::detect),
+        allowed_extensions,
+        allowed_mime_types,
+        tags,
+        schedule,
+        location,
+        generate_palettes: config_file.generate_palettes.unwrap_or(false),
+        on_change: config_file.on_change.unwrap_or_default(),
+        hook_timeout: Duration::from_secs(hook_timeout),
+        history_size: config_file.history_size.unwrap_or(DEFAULT_HISTORY_SIZE),
+        random_avoid_recent: config_file
+            .random_avoid_recent
+            .unwrap_or(DEFAULT_RANDOM_AVOID_RECENT),
+        ratings_path,
+        log_file: config_file.log_file,
+        log_max_size,
     })
 }
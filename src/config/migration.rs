@@ -0,0 +1,121 @@
+use toml::value::Table;
+
+/// The version of the configuration layout this build writes. Files without a
+/// `version` key are version 0.
+pub const CURRENT_CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a configuration from version `n` to version `n + 1`.
+/// When a key is renamed or restructured, bump `CURRENT_CONFIG_VERSION` and add
+/// the migration here, so older files keep working.
+const MIGRATIONS: [fn(&mut Table); CURRENT_CONFIG_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Version 1 only introduced the `version` key itself.
+fn migrate_v0_to_v1(_table: &mut Table) {}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("The configuration key \"version\" must be a non-negative integer")]
+    InvalidVersion,
+    #[error("The configuration is at version {0}, but this build only understands up to version {CURRENT_CONFIG_VERSION}. Please update wallpaper-manager")]
+    TooNew(u32),
+}
+
+/// Reads the layout version of a parsed configuration.
+pub fn config_version(table: &Table) -> Result<u32, MigrationError> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(toml::Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| MigrationError::InvalidVersion)
+        }
+        Some(_) => Err(MigrationError::InvalidVersion),
+    }
+}
+
+/// What `migrate` did to a configuration.
+pub struct Migrated {
+    /// The version it was at before.
+    pub original_version: u32,
+    /// Whether any key other than `version` changed.
+    pub changed_keys: bool,
+}
+
+/// Upgrades a parsed configuration, in place, to the current layout.
+pub fn migrate(table: &mut Table) -> Result<Migrated, MigrationError> {
+    let original_version = config_version(table)?;
+    if original_version > CURRENT_CONFIG_VERSION {
+        return Err(MigrationError::TooNew(original_version));
+    }
+
+    let mut original = table.clone();
+    original.remove("version");
+    for migration in &MIGRATIONS[original_version as usize..] {
+        migration(table);
+    }
+    table.remove("version");
+    let changed_keys = *table != original;
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(CURRENT_CONFIG_VERSION.into()),
+    );
+
+    Ok(Migrated {
+        original_version,
+        changed_keys,
+    })
+}
+
+/// Sets `version` to the current one in the text of a configuration, keeping
+/// everything else as it is. Only right when no other key changed.
+pub fn set_version_line(contents: &str) -> String {
+    let version_line = format!("version = {}", CURRENT_CONFIG_VERSION);
+    let mut lines: Vec<&str> = contents.lines().collect();
+    // Top-level keys come before the first table
+    let top_level = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..top_level].iter().position(|line| {
+        line.trim_start()
+            .strip_prefix("version")
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    });
+    match existing {
+        Some(index) => lines[index] = &version_line,
+        None => lines.insert(0, &version_line),
+    }
+    let mut upgraded = lines.join("\n");
+    if contents.is_empty() || contents.ends_with('\n') {
+        upgraded.push('\n');
+    }
+    upgraded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_version_line_adds_it_above_everything_else() {
+        assert_eq!(
+            set_version_line("# Mine\nwallpapers_dir = \"~/walls\"\n"),
+            "version = 1\n# Mine\nwallpapers_dir = \"~/walls\"\n"
+        );
+    }
+
+    #[test]
+    fn set_version_line_replaces_a_top_level_version() {
+        assert_eq!(
+            set_version_line("version = 0 # old\n[[schedule]]\nversion = 0\n"),
+            "version = 1\n[[schedule]]\nversion = 0\n"
+        );
+    }
+
+    #[test]
+    fn migrate_reports_whether_keys_changed() {
+        let mut table: Table = toml::from_str("cache_dir = \"/tmp\"").unwrap();
+        let migrated = migrate(&mut table).unwrap();
+        assert_eq!(migrated.original_version, 0);
+        assert!(!migrated.changed_keys);
+        assert_eq!(table.get("version"), Some(&toml::Value::Integer(1)));
+    }
+}
@@ -1,6 +1,18 @@
 mod config_file;
 mod full_config;
+mod migration;
+mod programs;
+mod schedule;
+mod validation;
 
+pub use config_file::ConfigFile;
 pub use full_config::read as read_config;
+pub use full_config::resolve_config_path;
 pub use full_config::ConfigResolution;
 pub use full_config::FullConfig as Config;
+pub use full_config::RendererKind;
+pub use migration::CURRENT_CONFIG_VERSION;
+pub use programs::Program;
+#[cfg(test)]
+pub use schedule::ScheduleEntry;
+pub use schedule::{ScheduleStart, ScheduleTarget};
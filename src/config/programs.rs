@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::config_file::ConfigFile;
+use super::full_config::ConfigResolution;
+
+/// An external program, and the arguments configured for it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Program {
+    pub path: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl Program {
+    fn new(path: Option<PathBuf>, default_name: &str, args: Option<Vec<String>>) -> Self {
+        Program {
+            path: path.unwrap_or_else(|| PathBuf::from(default_name)),
+            args: args.unwrap_or_default(),
+        }
+    }
+
+    /// A command for this program, without any arguments.
+    pub fn command(&self) -> Command {
+        Command::new(&self.path)
+    }
+
+    pub fn name(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+/// The program that sets still images as the X11 root window's background,
+/// without the xwinwrap and mpv daemon.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StaticSetter {
+    #[default]
+    Feh,
+    Xwallpaper,
+    Hsetroot,
+}
+
+impl StaticSetter {
+    fn program_name(self) -> &'static str {
+        match self {
+            StaticSetter::Feh => "feh",
+            StaticSetter::Xwallpaper => "xwallpaper",
+            StaticSetter::Hsetroot => "hsetroot",
+        }
+    }
+
+    /// The flags that make the image fill the screen. Each program takes the
+    /// image right after them.
+    fn default_args(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            StaticSetter::Feh => &["--no-fehbg", "--bg-fill"],
+            StaticSetter::Xwallpaper => &["--zoom"],
+            StaticSetter::Hsetroot => &["-fill"],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+}
+
+/// Every external program the tool runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Programs {
+    /// `args` go right before the output file when rescaling, so they can set
+    /// encoding options.
+    pub ffmpeg: Program,
+    /// `args` go right before the input file.
+    pub ffprobe: Program,
+    /// `args` are added after the default `-t -o -r`.
+    pub sxiv: Program,
+    /// `args` are added after the default mpv flags, so they take precedence.
+    pub mpv: Program,
+    /// `args` are every flag given to xwinwrap, before the `--` that starts mpv's
+    /// command line. Configuring them replaces the defaults.
+    pub xwinwrap: Program,
+    /// Plays videos on Wayland. `args` go right before the output name.
+    pub mpvpaper: Program,
+    /// Shows still images on Wayland. `args` are added after `swww img`.
+    pub swww: Program,
+    /// The process `swww` talks to, started by the daemon on Wayland.
+    pub swww_daemon: Program,
+    /// Sets still images on X11. `args` go right before the image, and
+    /// configuring them replaces the defaults of the chosen setter.
+    pub static_setter: Program,
+}
+
+impl Programs {
+    pub fn from_config_file(config_file: &ConfigFile, resolution: &ConfigResolution) -> Self {
+        let default_xwinwrap_args = || {
+            [
+                "-ov",
+                "-b",
+                "-fs",
+                "-g",
+                &format!("{}x{}+0+0", resolution.width, resolution.height),
+            ]
+            .map(String::from)
+            .to_vec()
+        };
+        let static_setter = config_file.static_setter.unwrap_or_default();
+
+        Programs {
+            ffmpeg: Program::new(
+                config_file.ffmpeg_path.clone(),
+                "ffmpeg",
+                config_file.ffmpeg_extra_args.clone(),
+            ),
+            ffprobe: Program::new(
+                config_file.ffprobe_path.clone(),
+                "ffprobe",
+                config_file.ffprobe_extra_args.clone(),
+            ),
+            sxiv: Program::new(
+                config_file.sxiv_path.clone(),
+                "sxiv",
+                config_file.sxiv_extra_args.clone(),
+            ),
+            mpv: Program::new(
+                config_file.mpv_path.clone(),
+                "mpv",
+                config_file.mpv_extra_args.clone(),
+            ),
+            xwinwrap: Program::new(
+                config_file.xwinwrap_path.clone(),
+                "xwinwrap",
+                Some(
+                    config_file
+                        .xwinwrap_args
+                        .clone()
+                        .unwrap_or_else(default_xwinwrap_args),
+                ),
+            ),
+            mpvpaper: Program::new(
+                config_file.mpvpaper_path.clone(),
+                "mpvpaper",
+                config_file.mpvpaper_extra_args.clone(),
+            ),
+            swww: Program::new(
+                config_file.swww_path.clone(),
+                "swww",
+                config_file.swww_extra_args.clone(),
+            ),
+            swww_daemon: Program::new(config_file.swww_daemon_path.clone(), "swww-daemon", None),
+            static_setter: Program::new(
+                config_file.static_setter_path.clone(),
+                static_setter.program_name(),
+                Some(
+                    config_file
+                        .static_setter_args
+                        .clone()
+                        .unwrap_or_else(|| static_setter.default_args()),
+                ),
+            ),
+        }
+    }
+}
+
+impl Default for Programs {
+    fn default() -> Self {
+        Self::from_config_file(&ConfigFile::default(), &ConfigResolution::default())
+    }
+}
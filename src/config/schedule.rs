@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+
+use chrono::NaiveTime;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::solar::SolarEvent;
+
+/// An entry of the `schedule` list, as written in the configuration. It lasts
+/// from its start until the next entry's start, and names exactly one of a
+/// wallpaper, a tag or a sub-folder of the wallpapers directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigScheduleEntry {
+    /// A time of day, like `"18:30"`, or a solar event with an optional offset,
+    /// like `"sunset"` or `"sunrise+00:30"`
+    pub start: String,
+    pub wallpaper: Option<PathBuf>,
+    pub tag: Option<String>,
+    pub folder: Option<PathBuf>,
+}
+
+/// What to play during a schedule entry. Paths are relative to the wallpapers
+/// directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ScheduleTarget {
+    Wallpaper(PathBuf),
+    Tag(String),
+    Folder(PathBuf),
+}
+
+/// When a schedule entry starts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ScheduleStart {
+    Time(NaiveTime),
+    /// Moves with the seasons. Needs a `location`.
+    Solar {
+        event: SolarEvent,
+        offset_minutes: i64,
+    },
+}
+
+impl ScheduleStart {
+    fn parse(text: &str) -> Option<ScheduleStart> {
+        let text = text.trim();
+        if let Ok(time) = NaiveTime::parse_from_str(text, "%H:%M") {
+            return Some(ScheduleStart::Time(time));
+        }
+
+        let (event, offset_minutes) = match text.find(['+', '-']) {
+            None => (text, 0),
+            Some(sign_index) => {
+                let (hours, minutes) = text[sign_index + 1..].trim().split_once(':')?;
+                let minutes = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+                let sign = if text[sign_index..].starts_with('-') {
+                    -1
+                } else {
+                    1
+                };
+                (&text[..sign_index], sign * minutes)
+            }
+        };
+        Some(ScheduleStart::Solar {
+            event: SolarEvent::from_name(event.trim())?,
+            offset_minutes,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleEntry {
+    pub start: ScheduleStart,
+    pub target: ScheduleTarget,
+}
+
+/// Where the screen is, to know when the sun rises and sets there. In degrees,
+/// north and east being positive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl ConfigScheduleEntry {
+    /// Checks the entry. Problems are returned along with the key they concern,
+    /// if it's not the entry as a whole.
+    pub fn resolve(&self) -> Result<ScheduleEntry, (Option<&'static str>, String)> {
+        let start = ScheduleStart::parse(&self.start).ok_or_else(|| {
+            (
+                Some("start"),
+                format!(
+                    "invalid start \"{}\", expected HH:MM, or one of dawn, sunrise, sunset or dusk with an optional offset like \"sunset-01:00\"",
+                    self.start
+                ),
+            )
+        })?;
+
+        let target = match (&self.wallpaper, &self.tag, &self.folder) {
+            (Some(wallpaper), None, None) => ScheduleTarget::Wallpaper(wallpaper.clone()),
+            (None, Some(tag), None) => ScheduleTarget::Tag(tag.clone()),
+            (None, None, Some(folder)) => ScheduleTarget::Folder(folder.clone()),
+            _ => {
+                return Err((
+                    None,
+                    "set exactly one of wallpaper, tag or folder".to_string(),
+                ))
+            }
+        };
+
+        Ok(ScheduleEntry { start, target })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solar(text: &str) -> Option<(SolarEvent, i64)> {
+        match ScheduleStart::parse(text)? {
+            ScheduleStart::Solar {
+                event,
+                offset_minutes,
+            } => Some((event, offset_minutes)),
+            ScheduleStart::Time(_) => None,
+        }
+    }
+
+    #[test]
+    fn parse_reads_times_of_day() {
+        assert!(matches!(
+            ScheduleStart::parse(" 18:30 "),
+            Some(ScheduleStart::Time(time)) if time == NaiveTime::from_hms_opt(18, 30, 0).unwrap()
+        ));
+    }
+
+    #[test]
+    fn parse_reads_solar_events_and_offsets() {
+        assert_eq!(solar("sunset"), Some((SolarEvent::Sunset, 0)));
+        assert_eq!(solar("sunrise+00:30"), Some((SolarEvent::Sunrise, 30)));
+        assert_eq!(solar("dusk - 01:15"), Some((SolarEvent::Dusk, -75)));
+    }
+
+    #[test]
+    fn parse_rejects_anything_else() {
+        for text in ["", "25:00", "noon", "sunset+30", "sunset+aa:bb"] {
+            assert!(ScheduleStart::parse(text).is_none(), "{}", text);
+        }
+    }
+}
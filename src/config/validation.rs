@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::config_file::{ConfigFile, ENV_VAR_PREFIX};
+
+/// Something wrong with the configuration, and where it was found.
+#[derive(Debug)]
+pub struct ConfigProblem {
+    /// Where the offending value was set, like `config.toml:3:1` or an environment
+    /// variable's name.
+    pub origin: Option<String>,
+    /// The offending key, like `resolution.width`.
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(origin) = &self.origin {
+            write!(f, "{}: ", origin)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Every problem found in the configuration, so they can all be fixed at once.
+#[derive(Debug, thiserror::Error)]
+pub struct ConfigProblems(pub Vec<ConfigProblem>);
+
+impl fmt::Display for ConfigProblems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds where keys are written in a configuration file, to point problems at
+/// the right line.
+pub struct KeyLocator {
+    path: PathBuf,
+    contents: String,
+}
+
+impl KeyLocator {
+    pub fn new(path: &Path, contents: &str) -> Self {
+        KeyLocator {
+            path: path.to_path_buf(),
+            contents: contents.to_string(),
+        }
+    }
+
+    /// An empty locator, for when the file can't be read.
+    pub fn empty(path: &Path) -> Self {
+        Self::new(path, "")
+    }
+
+    /// Formats a 0 based line and column as `file:line:column`.
+    pub fn format_position(&self, line: usize, column: usize) -> String {
+        format!(
+            "{}:{}:{}",
+            self.path.to_string_lossy(),
+            line + 1,
+            column + 1
+        )
+    }
+
+    /// Finds the position of a dotted key path, like `resolution.width`, where
+    /// entries of arrays of tables are numbered, like `schedule.0.start`. Handles
+    /// `key = value` lines, `[table]` and `[[array]]` headers and inline tables,
+    /// which is all the configuration uses.
+    pub fn locate(&self, key_path: &str) -> Option<String> {
+        let mut section = String::new();
+        // How many `[[array]]` headers of each name were seen, to number their entries
+        let mut array_lengths: HashMap<String, usize> = HashMap::new();
+        // The line of the most specific table header containing the key
+        let mut closest_header: Option<(usize, usize)> = None;
+        for (line_number, line) in self.contents.lines().enumerate() {
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            if let Some(header) = trimmed.strip_prefix('[') {
+                let name = header
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                section = if header.starts_with('[') {
+                    let length = array_lengths.entry(name.clone()).or_default();
+                    *length += 1;
+                    format!("{}.{}", name, *length - 1)
+                } else {
+                    name
+                };
+                let contains_key =
+                    key_path == section || key_path.starts_with(&format!("{}.", section));
+                if contains_key && closest_header.is_none_or(|(len, _)| section.len() > len) {
+                    closest_header = Some((section.len(), line_number));
+                }
+                continue;
+            }
+
+            let Some(key) = line_key(trimmed) else {
+                continue;
+            };
+            let full_key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", section, key)
+            };
+            if full_key == key_path {
+                return Some(self.format_position(line_number, indent));
+            }
+            if let Some(nested) = key_path.strip_prefix(&format!("{}.", full_key)) {
+                // Point at the nested key, in an inline table
+                let nested = nested.rsplit('.').next().unwrap_or(nested);
+                let column = line[indent..]
+                    .find(nested)
+                    .map_or(indent, |column| indent + column);
+                return Some(self.format_position(line_number, column));
+            }
+        }
+
+        closest_header.map(|(_, line)| self.format_position(line, 0))
+    }
+}
+
+/// The key assigned in a `key = value` line.
+fn line_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    Some(key.trim().trim_matches('"'))
+}
+
+/// Splits a toml deserialization error into its message and the key it refers
+/// to. The key is only part of the error's text, as "for key `...`".
+pub fn split_toml_error(error: &toml::de::Error) -> (String, Option<String>) {
+    let message = error.to_string();
+    // The position is reported separately
+    let message = match message.split_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    };
+    let Some((message, rest)) = message.split_once(" for key `") else {
+        return (message, None);
+    };
+    let key = rest.split('`').next().map(String::from);
+    (message.to_string(), key)
+}
+
+/// Tells which layer the final value of each configuration key comes from, so
+/// problems point at the right place.
+pub struct Origins {
+    file: KeyLocator,
+    profile: Option<String>,
+    profile_keys: Vec<String>,
+    env_keys: Vec<String>,
+    args_keys: Vec<String>,
+}
+
+impl Origins {
+    pub fn new(
+        file: KeyLocator,
+        profile: Option<(&str, &ConfigFile)>,
+        env: &ConfigFile,
+        args: &ConfigFile,
+    ) -> Self {
+        Origins {
+            file,
+            profile: profile.map(|(name, _)| name.to_string()),
+            profile_keys: profile
+                .map(|(_, layer)| set_keys(layer))
+                .unwrap_or_default(),
+            env_keys: set_keys(env),
+            args_keys: set_keys(args),
+        }
+    }
+
+    pub fn of(&self, key: &str) -> Option<String> {
+        let top_level_key = key.split('.').next().unwrap_or(key);
+        if self.args_keys.iter().any(|k| k == top_level_key) {
+            return Some("command line".to_string());
+        }
+        if self.env_keys.iter().any(|k| k == top_level_key) {
+            return Some(format!(
+                "environment variable {}{}",
+                ENV_VAR_PREFIX,
+                top_level_key.to_ascii_uppercase()
+            ));
+        }
+        if let Some(profile) = &self.profile {
+            if self.profile_keys.iter().any(|k| k == top_level_key) {
+                return self.file.locate(&format!("profiles.{}.{}", profile, key));
+            }
+        }
+        self.file.locate(key)
+    }
+
+    /// A problem with the final value of `key`.
+    pub fn problem(&self, key: &str, message: String) -> ConfigProblem {
+        ConfigProblem {
+            origin: self.of(key),
+            key: Some(key.to_string()),
+            message,
+        }
+    }
+}
+
+/// The keys a configuration layer sets.
+fn set_keys(config_file: &ConfigFile) -> Vec<String> {
+    match toml::Value::try_from(config_file) {
+        Ok(toml::Value::Table(table)) => table.keys().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"wallpapers_dir = "~/walls"
+  cache_dir = "~/cache"
+location = { latitude = 48.85, longitude = 2.35 }
+
+[resolution]
+width = 1920
+  height = 1080
+
+[profiles.work]
+cache_dir = "/work"
+
+[[schedule]]
+start = "07:00"
+tag = "day"
+
+[[schedule]]
+start = "sunset"
+tag = "night"
+"#;
+
+    fn locate(key_path: &str) -> Option<String> {
+        KeyLocator::new(Path::new("config.toml"), CONFIG).locate(key_path)
+    }
+
+    #[test]
+    fn locate_finds_top_level_keys() {
+        assert_eq!(locate("wallpapers_dir").as_deref(), Some("config.toml:1:1"));
+        assert_eq!(locate("cache_dir").as_deref(), Some("config.toml:2:3"));
+    }
+
+    #[test]
+    fn locate_finds_keys_of_tables() {
+        assert_eq!(
+            locate("resolution.width").as_deref(),
+            Some("config.toml:6:1")
+        );
+        assert_eq!(
+            locate("resolution.height").as_deref(),
+            Some("config.toml:7:3")
+        );
+        assert_eq!(
+            locate("profiles.work.cache_dir").as_deref(),
+            Some("config.toml:10:1")
+        );
+    }
+
+    #[test]
+    fn locate_numbers_entries_of_arrays_of_tables() {
+        assert_eq!(
+            locate("schedule.0.start").as_deref(),
+            Some("config.toml:13:1")
+        );
+        assert_eq!(
+            locate("schedule.1.tag").as_deref(),
+            Some("config.toml:18:1")
+        );
+    }
+
+    #[test]
+    fn locate_points_inside_inline_tables() {
+        assert_eq!(
+            locate("location.longitude").as_deref(),
+            Some("config.toml:3:32")
+        );
+    }
+
+    #[test]
+    fn locate_falls_back_to_the_closest_header() {
+        assert_eq!(
+            locate("resolution.depth").as_deref(),
+            Some("config.toml:5:1")
+        );
+        assert_eq!(locate("schedule.1").as_deref(), Some("config.toml:16:1"));
+        assert_eq!(locate("tags"), None);
+    }
+}
@@ -0,0 +1,88 @@
+pub mod protocol;
+mod server;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use crate::config::Config;
+use protocol::{Action, Event, Reply, ReplyBody, Request, Status, PROTOCOL_VERSION};
+
+pub use server::{listen, Controller};
+
+/// A connection to the daemon's control socket.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connects to the daemon. `None` if it's not running.
+    pub fn connect(config: &Config) -> Option<Client> {
+        let writer = UnixStream::connect(&config.control_socket_path).ok()?;
+        let reader = BufReader::new(writer.try_clone().ok()?);
+        Some(Client { reader, writer })
+    }
+
+    /// Connects to the daemon, failing with a hint to start it if it's not running.
+    pub fn connect_or_fail(config: &Config) -> Result<Client, String> {
+        Client::connect(config).ok_or_else(|| {
+            format!(
+                "The daemon is not running, or not listening on {}. Start it with `wallpaper-manager daemon`.",
+                config.control_socket_path.to_string_lossy()
+            )
+        })
+    }
+
+    fn read_reply(&mut self) -> Result<ReplyBody, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => return Err("The daemon closed the connection".to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(format!("Could not read from the daemon: {}", e)),
+        }
+        let reply: Reply = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid reply from the daemon: {}", e))?;
+        match reply.body {
+            ReplyBody::Error { message } => Err(message),
+            body => Ok(body),
+        }
+    }
+
+    /// Sends a request and waits for its reply.
+    pub fn send(&mut self, action: Action) -> Result<ReplyBody, String> {
+        let mut line = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            action,
+        })
+        .map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Could not write to the daemon: {}", e))?;
+        self.read_reply()
+    }
+
+    pub fn status(&mut self) -> Result<Status, String> {
+        match self.send(Action::Status)? {
+            ReplyBody::Status(status) => Ok(status),
+            body => Err(format!("Unexpected reply from the daemon: {:?}", body)),
+        }
+    }
+
+    /// Calls `on_event` for every change, until the daemon exits.
+    pub fn subscribe(mut self, mut on_event: impl FnMut(Event)) -> Result<(), String> {
+        self.send(Action::Subscribe)?;
+        loop {
+            match self.read_reply() {
+                Ok(ReplyBody::Event(event)) => on_event(event),
+                Ok(_) => {}
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Sends a request to the running daemon.
+pub fn send(config: &Config, action: Action) -> Result<ReplyBody, String> {
+    Client::connect_or_fail(config)?.send(action)
+}
@@ -0,0 +1,170 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Messages on the control socket are JSON objects, one per line, each carrying
+/// this version. It's bumped whenever a message changes in a way the other side
+/// can't read.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Sent by clients, like `{"version": 1, "action": "set", "wallpaper": "forest.mp4"}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    pub version: u32,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    /// Plays a wallpaper, given relative to the wallpapers directory, or as the
+    /// path of the file, its thumbnail or its rescaled copy. Written like
+    /// `path_to_key` does, as JSON strings must be valid UTF-8.
+    Set {
+        wallpaper: String,
+    },
+    /// After going back in the history, goes forward again. Otherwise plays the
+    /// wallpaper after the current one, in the order of their paths, or in the
+    /// order of `tag`.
+    Next {
+        #[serde(default)]
+        tag: Option<String>,
+    },
+    /// Goes back to the wallpaper shown before the current one, or plays the one
+    /// before it in the order of `tag`.
+    Previous {
+        #[serde(default)]
+        tag: Option<String>,
+    },
+    /// Plays a wallpaper picked at random, out of the ones of `tag` and inside
+    /// `folder` when given, weighted by their ratings. The wallpapers shown last
+    /// are avoided.
+    Random {
+        #[serde(default)]
+        tag: Option<String>,
+        /// Written like `path_to_key` does.
+        #[serde(default)]
+        folder: Option<String>,
+    },
+    /// Pauses or resumes the video. Toggles when `paused` is missing.
+    Pause {
+        #[serde(default)]
+        paused: Option<bool>,
+    },
+    Status,
+    /// Reads the configuration again. The renderer's settings only apply once
+    /// the daemon is restarted.
+    ReloadConfig,
+    /// Keeps the connection open, and sends an event after every change.
+    Subscribe,
+}
+
+/// Sent by the daemon: one for each request, then the events of a subscription.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reply {
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: ReplyBody,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ReplyBody {
+    /// Every action but `status` succeeded.
+    Ok,
+    Status(Status),
+    Error {
+        message: String,
+    },
+    Event(Event),
+}
+
+/// What the daemon is playing. Paths are written like `path_to_key` does, as
+/// JSON strings must be valid UTF-8.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Status {
+    /// Relative to the wallpapers directory. `None` until a wallpaper is played
+    /// through the daemon.
+    pub wallpaper: Option<String>,
+    /// The original file.
+    pub path: Option<String>,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Event {
+    pub event: EventKind,
+    /// The status right after the change.
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    WallpaperChanged,
+    Paused,
+    Resumed,
+    ConfigReloaded,
+}
+
+impl EventKind {
+    /// The name used in messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::WallpaperChanged => "wallpaper-changed",
+            EventKind::Paused => "paused",
+            EventKind::Resumed => "resumed",
+            EventKind::ConfigReloaded => "config-reloaded",
+        }
+    }
+}
+
+impl Reply {
+    pub fn new(body: ReplyBody) -> Self {
+        Reply {
+            version: PROTOCOL_VERSION,
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use super::*;
+    use crate::utils::{key_to_path, path_to_key};
+
+    fn round_trip(action: Action) -> Action {
+        let line = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            action,
+        })
+        .unwrap();
+        serde_json::from_str::<Request>(&line).unwrap().action
+    }
+
+    #[test]
+    fn paths_that_are_not_utf8_survive_a_request() {
+        let path = Path::new(OsStr::from_bytes(b"forest/r\xe2in\xff.mp4"));
+        let Action::Set { wallpaper } = round_trip(Action::Set {
+            wallpaper: path_to_key(path),
+        }) else {
+            panic!("expected a set request");
+        };
+        assert_eq!(key_to_path(&wallpaper), path);
+
+        let folder = Path::new(OsStr::from_bytes(b"f\x80lder"));
+        let Action::Random {
+            folder: Some(key), ..
+        } = round_trip(Action::Random {
+            tag: None,
+            folder: Some(path_to_key(folder)),
+        })
+        else {
+            panic!("expected a random request with a folder");
+        };
+        assert_eq!(key_to_path(&key), folder);
+    }
+}
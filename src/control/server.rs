@@ -0,0 +1,358 @@
+use std::fs::remove_file;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use super::protocol::{
+    Action, Event, EventKind, Reply, ReplyBody, Request, Status, PROTOCOL_VERSION,
+};
+use crate::config::{read_config, Config};
+use crate::history::{self, History};
+use crate::library::{Library, Wallpaper};
+use crate::mpv;
+use crate::ratings;
+use crate::utils::{key_to_path, path_to_key};
+use crate::Args;
+
+/// Subscribers not reading their events for this long are dropped.
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct State {
+    config: Config,
+    current: Option<Wallpaper>,
+    paused: bool,
+    /// How many entries back in the history the current wallpaper is, after
+    /// going back with `previous`. 0 when it's the latest one.
+    history_position: usize,
+    subscribers: Vec<UnixStream>,
+}
+
+impl State {
+    fn status(&self) -> Status {
+        Status {
+            wallpaper: self
+                .current
+                .as_ref()
+                .map(|wallpaper| path_to_key(wallpaper.id.as_path())),
+            path: self
+                .current
+                .as_ref()
+                .map(|wallpaper| path_to_key(&wallpaper.source)),
+            paused: self.paused,
+        }
+    }
+
+    /// Sends the event to every subscriber, forgetting the ones that are gone.
+    fn notify(&mut self, event: EventKind) {
+        let line = match to_line(&Reply::new(ReplyBody::Event(Event {
+            event,
+            status: self.status(),
+        }))) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Could not write the {} event: {}", event.name(), e);
+                return;
+            }
+        };
+        self.subscribers
+            .retain_mut(|subscriber| subscriber.write_all(line.as_bytes()).is_ok());
+    }
+}
+
+/// What the daemon is playing, changed by clients of the control socket and by
+/// the schedule. Every change goes through it, so subscribers hear about all of
+/// them.
+pub struct Controller {
+    args: Args,
+    state: Mutex<State>,
+}
+
+impl Controller {
+    pub fn new(args: Args, config: Config) -> Self {
+        Controller {
+            args,
+            state: Mutex::new(State {
+                config,
+                current: None,
+                paused: false,
+                history_position: 0,
+                subscribers: vec![],
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The configuration as of the last reload.
+    pub fn config(&self) -> Config {
+        self.state().config.clone()
+    }
+
+    /// Plays a wallpaper, runs the hooks in the background and tells subscribers.
+    pub fn show(&self, wallpaper: &Wallpaper) -> io::Result<()> {
+        self.play(wallpaper, None)
+    }
+
+    /// Plays a wallpaper. With a `history_position`, it comes from the history
+    /// and isn't added to it again.
+    fn play(&self, wallpaper: &Wallpaper, history_position: Option<usize>) -> io::Result<()> {
+        info!("Showing \"{}\"", wallpaper.id);
+        // Showing can take seconds, or minutes when the rescaled copy is generated
+        // again, during which status requests are still answered
+        crate::show_wallpaper(&self.config(), wallpaper)?;
+        let mut state = self.state();
+        // mpv keeps its pause state from one file to the next
+        if state.paused {
+            let _ = mpv::set_paused(&state.config.socket_path, false);
+        }
+        match history_position {
+            Some(position) => state.history_position = position,
+            None => {
+                history::record(&state.config, wallpaper);
+                state.history_position = 0;
+            }
+        }
+        state.current = Some(wallpaper.clone());
+        state.paused = false;
+        state.notify(EventKind::WallpaperChanged);
+        Ok(())
+    }
+
+    /// The closest entry of the history before or after the current one, which
+    /// is a different wallpaper still in the library, and its position.
+    fn step_through_history(
+        &self,
+        library: &Library,
+        backwards: bool,
+    ) -> Option<(Wallpaper, usize)> {
+        let (config, current, position) = {
+            let state = self.state();
+            let current = state.current.as_ref().map(|current| current.id.clone());
+            (state.config.clone(), current, state.history_position)
+        };
+        let history = History::read(&config.cache_dir);
+        let entries = history.entries();
+        let last = entries.len().checked_sub(1)?;
+        let mut index = last.checked_sub(position)?;
+        loop {
+            index = if backwards {
+                index.checked_sub(1)?
+            } else {
+                Some(index + 1).filter(|&index| index <= last)?
+            };
+            let id = entries[index].id();
+            if current.as_ref() == Some(&id) {
+                continue;
+            }
+            if let Some(wallpaper) = library.get(&id) {
+                return Some((wallpaper.clone(), last - index));
+            }
+        }
+    }
+
+    /// The wallpaper `offset` places away from the current one, out of
+    /// `wallpapers`, wrapping around.
+    fn neighbour(&self, wallpapers: &[&Wallpaper], offset: isize) -> Option<Wallpaper> {
+        if wallpapers.is_empty() {
+            return None;
+        }
+        let current = self.state().current.as_ref().and_then(|current| {
+            wallpapers
+                .iter()
+                .position(|wallpaper| wallpaper.id == current.id)
+        });
+        let count = wallpapers.len() as isize;
+        let index = match current {
+            Some(index) => (index as isize + offset).rem_euclid(count) as usize,
+            None if offset < 0 => wallpapers.len() - 1,
+            None => 0,
+        };
+        Some(wallpapers[index].clone())
+    }
+
+    /// The wallpaper `offset` places away from the current one, out of every
+    /// wallpaper or the ones of `tag`.
+    fn step(
+        &self,
+        library: &Library,
+        tag: Option<&str>,
+        offset: isize,
+    ) -> Result<Wallpaper, String> {
+        let config = self.config();
+        self.neighbour(&library.filter(&config, tag, None)?, offset)
+            .ok_or_else(|| "There are no wallpapers to pick from".to_string())
+    }
+
+    fn random(
+        &self,
+        library: &Library,
+        tag: Option<&str>,
+        folder: Option<&Path>,
+    ) -> Result<Wallpaper, String> {
+        let (config, current) = {
+            let state = self.state();
+            let current = state.current.as_ref().map(|current| current.id.clone());
+            (state.config.clone(), current)
+        };
+        let candidates = library.filter(&config, tag, folder)?;
+        ratings::pick_weighted(
+            &config,
+            &candidates,
+            current.as_ref(),
+            config.random_avoid_recent,
+        )
+        .cloned()
+        .ok_or_else(|| "There are no wallpapers to pick from".to_string())
+    }
+
+    fn pause(&self, paused: Option<bool>) -> Result<(), String> {
+        let mut state = self.state();
+        if state
+            .current
+            .as_ref()
+            .is_some_and(|wallpaper| wallpaper.is_still_image())
+        {
+            return Err("A still image is showing. There is nothing to pause.".to_string());
+        }
+        let paused = paused.unwrap_or(!state.paused);
+        mpv::set_paused(&state.config.socket_path, paused)
+            .map_err(|e| format!("Could not reach mpv: {}", e))?;
+        state.paused = paused;
+        state.notify(if paused {
+            EventKind::Paused
+        } else {
+            EventKind::Resumed
+        });
+        Ok(())
+    }
+
+    fn reload_config(&self) -> Result<(), String> {
+        let config = read_config(&self.args).map_err(|e| e.to_string())?;
+        let mut state = self.state();
+        state.config = config;
+        state.notify(EventKind::ConfigReloaded);
+        Ok(())
+    }
+
+    fn handle(&self, action: Action) -> Result<ReplyBody, String> {
+        let library = || Library::scan(&self.config());
+        let mut history_position = None;
+        let wallpaper = match action {
+            Action::Status => return Ok(ReplyBody::Status(self.state().status())),
+            Action::Pause { paused } => return self.pause(paused).map(|_| ReplyBody::Ok),
+            Action::ReloadConfig => return self.reload_config().map(|_| ReplyBody::Ok),
+            Action::Subscribe => unreachable!("subscriptions are handled by the connection"),
+            Action::Set { wallpaper } => library()?
+                .find(&key_to_path(&wallpaper))
+                .cloned()
+                .ok_or_else(|| format!("No wallpaper found at {}", wallpaper))?,
+            Action::Previous { tag: None } => {
+                let (wallpaper, position) = self
+                    .step_through_history(&library()?, true)
+                    .ok_or("There is nothing earlier in the history")?;
+                history_position = Some(position);
+                wallpaper
+            }
+            Action::Next { tag: None } if self.state().history_position > 0 => {
+                let library = library()?;
+                match self.step_through_history(&library, false) {
+                    Some((wallpaper, position)) => {
+                        history_position = Some(position);
+                        wallpaper
+                    }
+                    None => self.step(&library, None, 1)?,
+                }
+            }
+            Action::Next { tag } => self.step(&library()?, tag.as_deref(), 1)?,
+            Action::Previous { tag } => self.step(&library()?, tag.as_deref(), -1)?,
+            Action::Random { tag, folder } => {
+                let folder = folder.as_deref().map(key_to_path);
+                self.random(&library()?, tag.as_deref(), folder.as_deref())?
+            }
+        };
+        self.play(&wallpaper, history_position)
+            .map_err(|e| format!("Failed to show the wallpaper: {}", e))?;
+        Ok(ReplyBody::Ok)
+    }
+
+    /// Answers the requests of a client until it disconnects or subscribes.
+    fn serve(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let body = match serde_json::from_str::<Request>(&line) {
+                Err(e) => ReplyBody::Error {
+                    message: format!("Invalid request: {}", e),
+                },
+                Ok(request) if request.version != PROTOCOL_VERSION => ReplyBody::Error {
+                    message: format!(
+                        "Unsupported protocol version {}. The daemon speaks version {}.",
+                        request.version, PROTOCOL_VERSION
+                    ),
+                },
+                Ok(Request {
+                    action: Action::Subscribe,
+                    ..
+                }) => {
+                    writer.write_all(to_line(&Reply::new(ReplyBody::Ok))?.as_bytes())?;
+                    writer.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT))?;
+                    self.state().subscribers.push(writer);
+                    return Ok(());
+                }
+                Ok(request) => self
+                    .handle(request.action)
+                    .unwrap_or_else(|message| ReplyBody::Error { message }),
+            };
+            writer.write_all(to_line(&Reply::new(body))?.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn to_line(reply: &Reply) -> io::Result<String> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Listens on the control socket in the background. Fails if another daemon
+/// already does.
+pub fn listen(controller: Arc<Controller>, socket_path: &Path) -> io::Result<()> {
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "Another daemon is listening on {}",
+                socket_path.to_string_lossy()
+            ),
+        ));
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let controller = Arc::clone(&controller);
+            thread::spawn(move || {
+                if let Err(e) = controller.serve(stream) {
+                    warn!("Control socket client failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -1,25 +1,46 @@
+use crate::config::Program;
+use crate::runner::Runner;
 use crate::utils::{make_error_message_after_command_call, trim_string};
+use std::fs::remove_file;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 
-pub fn get_resolution(file_path: &Path) -> Result<(i32, i32), String> {
-    let resolution: String = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-select_streams",
-            "v:0",
-            "-show_entries",
-            "stream=width,height",
-            "-of",
-            "csv=p=0",
-            &file_path.to_string_lossy(),
-        ])
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|err| make_error_message_after_command_call("ffprobe", err))?
-        .wait_with_output()
-        .map_err(|_| "ffprobe failed".to_string())?
+/// A snapshot of an ongoing ffmpeg encode, as reported through `-progress`.
+#[derive(Debug, Default, Clone)]
+pub struct EncodeProgress {
+    /// How many seconds of the output have been encoded so far.
+    pub out_time: f64,
+    /// Encoding speed relative to realtime. `None` while ffmpeg reports N/A.
+    pub speed: Option<f64>,
+    /// Whether ffmpeg reported the encode as finished.
+    pub done: bool,
+}
+
+pub fn get_resolution(
+    runner: &Runner,
+    ffprobe: &Program,
+    file_path: &Path,
+) -> Result<(i32, i32), String> {
+    let resolution: String = runner
+        .output(
+            ffprobe
+                .command()
+                .args([
+                    "-v",
+                    "error",
+                    "-select_streams",
+                    "v:0",
+                    "-show_entries",
+                    "stream=width,height",
+                    "-of",
+                    "csv=p=0",
+                ])
+                .args(&ffprobe.args)
+                .arg(file_path)
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|err| make_error_message_after_command_call(&ffprobe.name(), err))?
         .stdout
         .into_iter()
         .map(|c| c as char)
@@ -45,51 +66,172 @@ pub fn get_resolution(file_path: &Path) -> Result<(i32, i32), String> {
     Ok((width, height))
 }
 
+/// Returns the duration of the file in seconds, or `None` if ffprobe can't tell
+/// (still images, for example).
+pub fn get_duration(
+    runner: &Runner,
+    ffprobe: &Program,
+    file_path: &Path,
+) -> Result<Option<f64>, String> {
+    let mut duration: String = runner
+        .output(
+            ffprobe
+                .command()
+                .args([
+                    "-v",
+                    "error",
+                    "-show_entries",
+                    "format=duration",
+                    "-of",
+                    "csv=p=0",
+                ])
+                .args(&ffprobe.args)
+                .arg(file_path)
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|err| make_error_message_after_command_call(&ffprobe.name(), err))?
+        .stdout
+        .into_iter()
+        .map(|c| c as char)
+        .collect::<String>();
+
+    Ok(trim_string(&mut duration).parse().ok())
+}
+
 pub fn rescale_video(
+    runner: &Runner,
+    ffmpeg: &Program,
     original_video_path: &Path,
     new_width: i32,
     new_height: i32,
     new_video_path: &Path,
+    mut on_progress: impl FnMut(&EncodeProgress),
 ) -> Result<(), String> {
-    Command::new("ffmpeg")
-        .args([
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-i",
-            &original_video_path.to_string_lossy(),
-            "-vf",
-            &format!("scale={}:{}", new_width, new_height),
-            &new_video_path.to_string_lossy(),
-        ])
-        .spawn()
-        .map_err(|err| make_error_message_after_command_call("ffmpeg", err))?
-        .wait()
-        .map_err(|_| "ffmpeg failed".to_string())?;
+    let Some(mut child) = runner
+        .spawn(
+            ffmpeg
+                .command()
+                .args([
+                    "-hide_banner",
+                    "-loglevel",
+                    "error",
+                    "-nostats",
+                    "-progress",
+                    "pipe:1",
+                    "-i",
+                ])
+                .arg(original_video_path)
+                .args(["-vf", &format!("scale={}:{}", new_width, new_height)])
+                .args(&ffmpeg.args)
+                .arg(new_video_path)
+                .stdout(Stdio::piped()),
+        )
+        .map_err(|err| make_error_message_after_command_call(&ffmpeg.name(), err))?
+    else {
+        return Ok(());
+    };
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| "Failed to capture ffmpeg output".to_string())?;
+
+    // ffmpeg writes blocks of `key=value` lines, each one terminated by a `progress` key.
+    let mut progress = EncodeProgress::default();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.map_err(|_| "Failed to read ffmpeg progress".to_string())?;
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            "out_time_us" => {
+                if let Ok(micros) = value.parse::<f64>() {
+                    progress.out_time = micros / 1_000_000.0;
+                }
+            }
+            "speed" => progress.speed = value.trim().trim_end_matches('x').parse().ok(),
+            "progress" => {
+                progress.done = value == "end";
+                on_progress(&progress);
+            }
+            _ => {}
+        }
+    }
+
+    let status = child.wait().map_err(|_| "ffmpeg failed".to_string())?;
+    if !status.success() {
+        return Err(format!("ffmpeg exited with {}", status));
+    }
 
     Ok(())
 }
 
-pub fn generate_thumbnail(original_video_path: &Path, thumbnail_path: &Path) -> Result<(), String> {
-    Command::new("ffmpeg")
-        .args([
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-i",
-            &original_video_path.to_string_lossy(),
-            "-ss",
-            "00:00:00.000",
-            "-vframes",
-            "1",
-            "-vf",
-            "scale=520:-1",
-            &*thumbnail_path.to_string_lossy(),
-        ])
-        .spawn()
-        .map_err(|err| make_error_message_after_command_call("ffmpeg", err))?
-        .wait()
-        .map_err(|_| "ffmpeg failed".to_string())?;
+pub fn generate_thumbnail(
+    runner: &Runner,
+    ffmpeg: &Program,
+    original_video_path: &Path,
+    thumbnail_path: &Path,
+) -> Result<(), String> {
+    let Some(mut child) = runner
+        .spawn(
+            ffmpeg
+                .command()
+                .args(["-hide_banner", "-loglevel", "error", "-i"])
+                .arg(original_video_path)
+                .args([
+                    "-ss",
+                    "00:00:00.000",
+                    "-vframes",
+                    "1",
+                    "-vf",
+                    "scale=520:-1",
+                ])
+                .arg(thumbnail_path),
+        )
+        .map_err(|err| make_error_message_after_command_call(&ffmpeg.name(), err))?
+    else {
+        return Ok(());
+    };
+    let status = child.wait().map_err(|_| "ffmpeg failed".to_string())?;
+    if !status.success() {
+        // Otherwise it would pass for a thumbnail that's already generated
+        let _ = remove_file(thumbnail_path);
+        return Err(format!("ffmpeg exited with {}", status));
+    }
 
     Ok(())
 }
+
+/// Decodes a single frame as raw RGB pixels, scaled down to `size`x`size`. For
+/// videos, the frame at `seek` seconds is used.
+pub fn decode_rgb_frame(
+    runner: &Runner,
+    ffmpeg: &Program,
+    file_path: &Path,
+    seek: Option<f64>,
+    size: u32,
+) -> Result<Vec<u8>, String> {
+    let mut command = ffmpeg.command();
+    command.args(["-hide_banner", "-loglevel", "error"]);
+    if let Some(seek) = seek {
+        command.arg("-ss").arg(seek.to_string());
+    }
+    let output = runner
+        .output(
+            command
+                .arg("-i")
+                .arg(file_path)
+                .args(["-frames:v", "1", "-vf"])
+                .arg(format!("scale={}:{}", size, size))
+                .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "pipe:1"])
+                .stderr(Stdio::inherit()),
+        )
+        .map_err(|err| make_error_message_after_command_call(&ffmpeg.name(), err))?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(format!(
+            "ffmpeg could not decode a frame of {}",
+            file_path.to_string_lossy()
+        ));
+    }
+    Ok(output.stdout)
+}
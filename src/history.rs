@@ -0,0 +1,107 @@
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::library::{Wallpaper, WallpaperId};
+use crate::output::{self, Message};
+use crate::utils::{key_to_path, path_to_key};
+
+const HISTORY_FILE_NAME: &str = "wallpapers-history.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Relative to the wallpapers directory, written by `path_to_key`.
+    pub wallpaper: String,
+    pub shown_at: DateTime<Local>,
+}
+
+impl HistoryEntry {
+    /// The wallpaper, to look up in the library.
+    pub fn id(&self) -> WallpaperId {
+        WallpaperId::new(key_to_path(&self.wallpaper))
+    }
+}
+
+/// The wallpapers shown last, oldest first, up to `history_size` of them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct History {
+    #[serde(default)]
+    shown: Vec<HistoryEntry>,
+}
+
+impl History {
+    fn path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join(HISTORY_FILE_NAME)
+    }
+
+    /// Reads the history. A missing or corrupted file is treated as empty.
+    pub fn read(cache_dir: &Path) -> History {
+        read_to_string(Self::path(cache_dir))
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, cache_dir: &Path) -> Result<(), String> {
+        let contents =
+            toml::to_string(self).map_err(|e| format!("Failed to serialize history: {}", e))?;
+        write(Self::path(cache_dir), contents)
+            .map_err(|e| format!("Failed to write history: {}", e))
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.shown
+    }
+
+    /// Adds the wallpaper as shown now, forgetting the oldest entries beyond `size`.
+    pub fn record(&mut self, wallpaper: &Wallpaper, size: usize) {
+        self.shown.push(HistoryEntry {
+            wallpaper: path_to_key(wallpaper.id.as_path()),
+            shown_at: Local::now(),
+        });
+        let excess = self.shown.len().saturating_sub(size);
+        self.shown.drain(..excess);
+    }
+}
+
+/// Adds a wallpaper that was just shown to the history.
+pub fn record(config: &Config, wallpaper: &Wallpaper) {
+    let mut history = History::read(&config.cache_dir);
+    history.record(wallpaper, config.history_size);
+    if let Err(e) = history.write(&config.cache_dir) {
+        warn!("{}", e);
+    }
+}
+
+/// Prints the wallpapers shown last, most recent first.
+pub fn print(config: &Config, limit: Option<usize>) {
+    let history = History::read(&config.cache_dir);
+    let entries: Vec<HistoryEntry> = history
+        .entries()
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    output::result(
+        Message::History {
+            entries: entries.clone(),
+        },
+        || {
+            if entries.is_empty() {
+                println!("No wallpaper was shown yet");
+            }
+            for entry in &entries {
+                println!(
+                    "{}  {}",
+                    entry.shown_at.format("%Y-%m-%d %H:%M:%S"),
+                    entry.wallpaper
+                );
+            }
+        },
+    );
+}
@@ -0,0 +1,109 @@
+use std::io::stderr;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::config::Config;
+use crate::library::Wallpaper;
+use crate::runner::Runner;
+
+/// How often a running hook is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Hooks started for a wallpaper change. Dropping this leaves them running in
+/// the background.
+pub struct RunningHooks(Vec<JoinHandle<()>>);
+
+impl RunningHooks {
+    /// Waits for every hook to finish or time out. Short-lived commands call this
+    /// before exiting, so timeouts are still enforced.
+    pub fn wait(self) {
+        for handle in self.0 {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The tags listing a wallpaper.
+fn tags_of<'a>(config: &'a Config, wallpaper: &Wallpaper) -> Vec<&'a str> {
+    config
+        .tags
+        .iter()
+        .filter(|(_, paths)| paths.iter().any(|path| path == wallpaper.id.as_path()))
+        .map(|(tag, _)| tag.as_str())
+        .collect()
+}
+
+/// Kills the hook if it runs for longer than `timeout`, and reports failures.
+fn watch(command_line: &str, mut child: Child, timeout: Duration) {
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return,
+            Ok(Some(status)) => {
+                warn!("Hook \"{}\" failed with {}", command_line, status);
+                return;
+            }
+            Ok(None) if started.elapsed() >= timeout => {
+                // The hook runs in its own process group, so this also kills what
+                // the shell started
+                // SAFETY: kill takes no pointers. The hook is our child and leads
+                // its own process group, which can't be reused until it's waited for.
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = child.wait();
+                warn!(
+                    "Hook \"{}\" did not finish within {} seconds. Killed it.",
+                    command_line,
+                    timeout.as_secs()
+                );
+                return;
+            }
+            Ok(None) => sleep(POLL_INTERVAL),
+            Err(e) => {
+                warn!("Could not wait for hook \"{}\": {}", command_line, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Starts every `on_change` hook for the wallpaper now playing, each in its own
+/// thread, so a slow or failing hook never holds back the change itself.
+pub fn run_on_change(config: &Config, wallpaper: &Wallpaper) -> RunningHooks {
+    let runner = Runner::default();
+    let tags = tags_of(config, wallpaper).join(",");
+
+    let mut handles = vec![];
+    for command_line in &config.on_change {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(command_line)
+            .env("WALLPAPER_PATH", &wallpaper.source)
+            .env("WALLPAPER_PLAYING_PATH", wallpaper.playable_path())
+            .env("WALLPAPER_THUMBNAIL", &wallpaper.thumbnail)
+            .env("WALLPAPER_NAME", wallpaper.id.as_path())
+            .env("WALLPAPER_TAGS", &tags)
+            .stdin(Stdio::null())
+            // stdout is kept for results, like the JSON of --output json
+            .stdout(stderr())
+            .process_group(0);
+
+        match runner.spawn(&mut command) {
+            Ok(Some(child)) => {
+                let command_line = command_line.clone();
+                let timeout = config.hook_timeout;
+                handles.push(thread::spawn(move || watch(&command_line, child, timeout)));
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not start hook \"{}\": {}", command_line, e),
+        }
+    }
+
+    RunningHooks(handles)
+}
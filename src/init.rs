@@ -0,0 +1,172 @@
+use std::fs::create_dir_all;
+use std::io::{stderr, stdin, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+
+use crate::config::{resolve_config_path, ConfigFile, ConfigResolution, CURRENT_CONFIG_VERSION};
+use crate::output::{self, Message};
+use crate::runner::Runner;
+use crate::Args;
+
+pub struct InitOptions {
+    /// Accept every detected or provided value without asking
+    pub yes: bool,
+    /// Overwrite an existing configuration file
+    pub force: bool,
+    pub resolution: Option<ConfigResolution>,
+}
+
+/// Shows a prompt and reads the answer, trimmed. Prompts go to stderr, so they
+/// don't mix with the result on stdout.
+fn read_answer(prompt: &str) -> String {
+    eprint!("{} ", prompt);
+    let _ = stderr().flush();
+    let mut answer = String::new();
+    let _ = stdin().read_line(&mut answer);
+    answer.trim().to_string()
+}
+
+/// Asks for a value, proposing `default`, which is used when the answer is empty.
+fn ask(prompt: &str, default: &str) -> String {
+    let answer = read_answer(&format!("{} [{}]:", prompt, default));
+    if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    }
+}
+
+/// Asks a yes or no question, yes being the default.
+fn confirm(prompt: &str) -> bool {
+    loop {
+        match read_answer(&format!("{} (Y/n)", prompt))
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" | "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => eprintln!("Please answer yes or no"),
+        }
+    }
+}
+
+/// Finds the current screen resolution through xrandr, if it's available.
+fn detect_resolution(runner: &Runner) -> Option<ConfigResolution> {
+    let output = runner
+        .output(Command::new("xrandr").arg("--current"))
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+
+    // The first line looks like "Screen 0: minimum 8 x 8, current 1920 x 1080, maximum ..."
+    let current = output
+        .lines()
+        .next()?
+        .split(',')
+        .find_map(|part| part.trim().strip_prefix("current "))?;
+    let (width, height) = current.split_once(" x ")?;
+    Some(ConfigResolution {
+        width: width.trim().parse().ok()?,
+        height: height.trim().parse().ok()?,
+    })
+}
+
+fn default_wallpapers_dir() -> PathBuf {
+    dirs::home_dir()
+        .map(|home| home.join("Wallpapers/live"))
+        .unwrap_or_else(|| PathBuf::from("Wallpapers/live"))
+}
+
+pub fn run(args: &Args, options: &InitOptions) -> anyhow::Result<()> {
+    let config_file_path = resolve_config_path(args)?;
+    if config_file_path.is_file() && !options.force {
+        anyhow::bail!(
+            "A configuration file already exists at {}. Use --force to overwrite it.",
+            config_file_path.to_string_lossy()
+        );
+    }
+
+    if !options.yes && !stdin().is_terminal() {
+        anyhow::bail!(
+            "Not running in a terminal. Use --yes to accept the defaults without asking."
+        );
+    }
+
+    let detected_resolution = options
+        .resolution
+        .clone()
+        .or_else(|| detect_resolution(&Runner::default()))
+        .unwrap_or_default();
+    let proposed_wallpapers_dir = args
+        .wallpapers_dir
+        .clone()
+        .unwrap_or_else(default_wallpapers_dir);
+    let proposed_cache_dir = args.cache_dir.clone().or_else(dirs::cache_dir);
+
+    let (wallpapers_dir, cache_dir, resolution) = if options.yes {
+        (
+            proposed_wallpapers_dir,
+            proposed_cache_dir,
+            detected_resolution,
+        )
+    } else {
+        let wallpapers_dir = PathBuf::from(ask(
+            "Where are your wallpapers?",
+            &proposed_wallpapers_dir.to_string_lossy(),
+        ));
+        let cache_dir = PathBuf::from(ask(
+            "Where should thumbnails and rescaled wallpapers be stored?",
+            &proposed_cache_dir
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ));
+        let resolution = loop {
+            let answer = ask(
+                "What is your screen resolution?",
+                &format!(
+                    "{}x{}",
+                    detected_resolution.width, detected_resolution.height
+                ),
+            );
+            match answer.parse::<ConfigResolution>() {
+                Ok(resolution) => break resolution,
+                Err(_) => eprintln!("Please write the resolution as WIDTHxHEIGHT, like 1920x1080"),
+            }
+        };
+        (wallpapers_dir, Some(cache_dir), resolution)
+    };
+
+    if !wallpapers_dir.is_dir()
+        && (options.yes
+            || confirm(&format!(
+                "{} does not exist. Create it?",
+                wallpapers_dir.to_string_lossy()
+            )))
+    {
+        create_dir_all(&wallpapers_dir)?;
+        info!("Created {}", wallpapers_dir.to_string_lossy());
+    }
+
+    ConfigFile {
+        version: Some(CURRENT_CONFIG_VERSION),
+        wallpapers_dir: Some(wallpapers_dir),
+        cache_dir: cache_dir.filter(|dir| dir != Path::new("")),
+        resolution: Some(resolution),
+        ..ConfigFile::default()
+    }
+    .write(&config_file_path)?;
+
+    output::result(
+        Message::ConfigWritten {
+            path: config_file_path.clone(),
+        },
+        || {
+            println!(
+                "Configuration written to {}",
+                config_file_path.to_string_lossy()
+            )
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::read_dir;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+use log::warn;
+
+use crate::cache::CacheUsage;
+use crate::config::Config;
+
+const THUMBNAIL_EXTENSION: &str = ".jpg";
+const PALETTE_EXTENSION: &str = ".json";
+/// Files with this name list gitignore-style patterns of files to leave out of the
+/// library, relative to the directory they're in.
+pub const IGNORE_FILE_NAME: &str = ".wallpaperignore";
+/// The MIME type of each extension wallpapers may have, for `allowed_mime_types`.
+const MEDIA_TYPES: [(&str, &str); 23] = [
+    ("mp4", "video/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("wmv", "video/x-ms-wmv"),
+    ("flv", "video/x-flv"),
+    ("mpg", "video/mpeg"),
+    ("mpeg", "video/mpeg"),
+    ("ogv", "video/ogg"),
+    ("ts", "video/mp2t"),
+    ("3gp", "video/3gpp"),
+    ("gif", "image/gif"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+];
+
+/// The MIME type of a file, going by its extension.
+fn media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    MEDIA_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Whether a MIME type matches a pattern like `video/mp4` or `video/*`.
+fn mime_type_matches(mime_type: &str, pattern: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(kind) => mime_type.split('/').next() == Some(kind),
+        None => mime_type == pattern,
+    }
+}
+
+/// Whether a file is a video or an image the configuration allows, going by its
+/// extension.
+fn is_allowed_media(config: &Config, path: &Path) -> bool {
+    let Some(extension) = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase)
+    else {
+        return false;
+    };
+    if config
+        .allowed_extensions
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&extension))
+    {
+        return true;
+    }
+    media_type(path).is_some_and(|mime_type| {
+        config
+            .allowed_mime_types
+            .iter()
+            .any(|pattern| mime_type_matches(mime_type, pattern))
+    })
+}
+
+/// Identifies a wallpaper by its path relative to the wallpapers directory. Its
+/// thumbnail and rescaled copy are named after it, so `sunset.mp4` and
+/// `sunset.webm` never share cache entries.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WallpaperId(PathBuf);
+
+impl WallpaperId {
+    pub fn new(relative_path: impl Into<PathBuf>) -> Self {
+        WallpaperId(relative_path.into())
+    }
+
+    /// The path relative to the wallpapers directory.
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Display for WallpaperId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.to_string_lossy())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Wallpaper {
+    pub id: WallpaperId,
+    /// The original file, inside the wallpapers directory.
+    pub source: PathBuf,
+    /// Where the thumbnail is, or would be, generated.
+    pub thumbnail: PathBuf,
+    /// Where the rescaled copy is, or would be, generated.
+    pub rescaled: PathBuf,
+    /// Where its colour palette is, or would be, cached.
+    pub palette: PathBuf,
+}
+
+impl Wallpaper {
+    /// The file mpv should play: the rescaled copy if there is one, or else the original.
+    pub fn playable_path(&self) -> &Path {
+        if self.rescaled.is_file() {
+            &self.rescaled
+        } else {
+            &self.source
+        }
+    }
+
+    /// Whether it's an image, shown still rather than played like a video. GIFs
+    /// are played, since they may be animated.
+    pub fn is_still_image(&self) -> bool {
+        media_type(&self.source)
+            .is_some_and(|mime_type| mime_type.starts_with("image/") && mime_type != "image/gif")
+    }
+}
+
+/// An index of every wallpaper, which resolves thumbnails and rescaled copies
+/// back to the wallpaper they belong to.
+pub struct Library {
+    wallpapers_dir: PathBuf,
+    thumbnails_cache_dir: PathBuf,
+    wallpapers_rescaled_dir: PathBuf,
+    palettes_cache_dir: PathBuf,
+    wallpapers: BTreeMap<WallpaperId, Wallpaper>,
+    /// Files that are neither ignored nor allowed media, relative to the
+    /// wallpapers directory.
+    skipped: Vec<PathBuf>,
+}
+
+impl Library {
+    pub fn scan(config: &Config) -> Result<Library, String> {
+        let mut library = Library {
+            wallpapers_dir: config.wallpapers_dir.clone(),
+            thumbnails_cache_dir: config.thumbnails_cache_dir.clone(),
+            wallpapers_rescaled_dir: config.wallpapers_rescaled_dir.clone(),
+            palettes_cache_dir: config.palettes_cache_dir.clone(),
+            wallpapers: BTreeMap::new(),
+            skipped: vec![],
+        };
+
+        // Past the root, a folder that can't be read or a bad line in an ignore
+        // file only leaves some wallpapers out
+        read_dir(&config.wallpapers_dir).map_err(|e| {
+            format!(
+                "Could not read wallpapers directory {}: {}",
+                config.wallpapers_dir.to_string_lossy(),
+                e
+            )
+        })?;
+        let walker = WalkBuilder::new(&config.wallpapers_dir)
+            .standard_filters(false)
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            .build();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            // Like a bad line in an ignore file, whose other lines still apply
+            if let Some(e) = entry.error() {
+                warn!("{}", e);
+            }
+            if !entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+            {
+                continue;
+            }
+            let source = entry.into_path();
+            let Ok(relative) = source.strip_prefix(&config.wallpapers_dir) else {
+                continue;
+            };
+            if relative.file_name() == Some(OsStr::new(IGNORE_FILE_NAME)) {
+                continue;
+            }
+            if !is_allowed_media(config, relative) {
+                library.skipped.push(relative.to_path_buf());
+                continue;
+            }
+            let id = WallpaperId(relative.to_path_buf());
+            let wallpaper = library.make_wallpaper(id.clone(), source.clone());
+            library.wallpapers.insert(id, wallpaper);
+        }
+        library.skipped.sort();
+
+        Ok(library)
+    }
+
+    fn make_wallpaper(&self, id: WallpaperId, source: PathBuf) -> Wallpaper {
+        let mut thumbnail: OsString = self.thumbnails_cache_dir.join(&id.0).into();
+        thumbnail.push(THUMBNAIL_EXTENSION);
+        let mut palette: OsString = self.palettes_cache_dir.join(&id.0).into();
+        palette.push(PALETTE_EXTENSION);
+        Wallpaper {
+            rescaled: self.wallpapers_rescaled_dir.join(&id.0),
+            thumbnail: thumbnail.into(),
+            palette: palette.into(),
+            source,
+            id,
+        }
+    }
+
+    /// Files of the wallpapers directory left out because they are not videos or
+    /// images, relative to it.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    /// Every wallpaper, ordered by id.
+    pub fn wallpapers(&self) -> impl Iterator<Item = &Wallpaper> {
+        self.wallpapers.values()
+    }
+
+    pub fn get(&self, id: &WallpaperId) -> Option<&Wallpaper> {
+        self.wallpapers.get(id)
+    }
+
+    /// Finds the wallpaper a path belongs to. The path may be the original file,
+    /// its thumbnail or its rescaled copy.
+    pub fn resolve(&self, path: &Path) -> Option<&Wallpaper> {
+        if let Ok(relative) = path.strip_prefix(&self.wallpapers_dir) {
+            return self.get(&WallpaperId(relative.to_path_buf()));
+        }
+        if let Ok(relative) = path.strip_prefix(&self.wallpapers_rescaled_dir) {
+            return self.get(&WallpaperId(relative.to_path_buf()));
+        }
+        if let Ok(relative) = path.strip_prefix(&self.thumbnails_cache_dir) {
+            let relative = relative.as_os_str().as_bytes();
+            let relative = relative
+                .strip_suffix(THUMBNAIL_EXTENSION.as_bytes())
+                .unwrap_or(relative);
+            return self.get(&WallpaperId(PathBuf::from(OsStr::from_bytes(relative))));
+        }
+        None
+    }
+
+    /// Finds a wallpaper by its path relative to the wallpapers directory, or by
+    /// any path `resolve` accepts.
+    pub fn find(&self, path: &Path) -> Option<&Wallpaper> {
+        self.get(&WallpaperId::new(path))
+            .or_else(|| self.resolve(path))
+    }
+
+    /// The wallpapers listed by a tag, in the order of the tag. Empty if there is
+    /// no such tag.
+    pub fn with_tag(&self, config: &Config, tag: &str) -> Vec<&Wallpaper> {
+        config
+            .tags
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .filter_map(|path| self.get(&WallpaperId::new(path)))
+            .collect()
+    }
+
+    /// The wallpapers inside a folder of the wallpapers directory.
+    pub fn in_folder(&self, folder: &Path) -> Vec<&Wallpaper> {
+        self.wallpapers()
+            .filter(|wallpaper| wallpaper.id.as_path().starts_with(folder))
+            .collect()
+    }
+
+    /// The wallpapers of a tag and inside a folder, or every wallpaper when
+    /// neither is given. Fails if the tag doesn't exist.
+    pub fn filter(
+        &self,
+        config: &Config,
+        tag: Option<&str>,
+        folder: Option<&Path>,
+    ) -> Result<Vec<&Wallpaper>, String> {
+        let mut wallpapers = match tag {
+            None => self.wallpapers().collect(),
+            Some(tag) if config.tags.contains_key(tag) => self.with_tag(config, tag),
+            Some(tag) => return Err(format!("There is no tag named {}", tag)),
+        };
+        if let Some(folder) = folder {
+            wallpapers.retain(|wallpaper| wallpaper.id.as_path().starts_with(folder));
+        }
+        Ok(wallpapers)
+    }
+
+    /// The wallpaper that was played last, out of the ones still in the library.
+    pub fn last_played(&self, config: &Config) -> Option<&Wallpaper> {
+        let usage = CacheUsage::read(&config.cache_dir);
+        self.wallpapers()
+            .filter_map(|wallpaper| {
+                let last_loaded = usage
+                    .last_loaded(&wallpaper.source)
+                    .max(usage.last_loaded(&wallpaper.rescaled))?;
+                Some((last_loaded, wallpaper))
+            })
+            .max_by_key(|(last_loaded, _)| *last_loaded)
+            .map(|(_, wallpaper)| wallpaper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(ids: &[&str]) -> Library {
+        let mut library = Library {
+            wallpapers_dir: PathBuf::from("/walls"),
+            thumbnails_cache_dir: PathBuf::from("/cache/wallpapers-thumbnail"),
+            wallpapers_rescaled_dir: PathBuf::from("/cache/wallpapers-rescaled"),
+            palettes_cache_dir: PathBuf::from("/cache/wallpapers-palette"),
+            wallpapers: BTreeMap::new(),
+            skipped: vec![],
+        };
+        for id in ids {
+            let wallpaper =
+                library.make_wallpaper(WallpaperId::new(id), Path::new("/walls").join(id));
+            library.wallpapers.insert(wallpaper.id.clone(), wallpaper);
+        }
+        library
+    }
+
+    fn resolved_id(library: &Library, path: &str) -> Option<String> {
+        library
+            .resolve(Path::new(path))
+            .map(|wallpaper| wallpaper.id.to_string())
+    }
+
+    #[test]
+    fn wallpapers_differing_by_extension_keep_their_own_cache_files() {
+        let library = library(&["sunset.mp4", "sunset.webm"]);
+        for id in ["sunset.mp4", "sunset.webm"] {
+            let wallpaper = library.get(&WallpaperId::new(id)).unwrap();
+            assert_eq!(
+                resolved_id(&library, &wallpaper.thumbnail.to_string_lossy()).as_deref(),
+                Some(id)
+            );
+            assert_eq!(
+                resolved_id(&library, &wallpaper.rescaled.to_string_lossy()).as_deref(),
+                Some(id)
+            );
+        }
+        assert_ne!(
+            library
+                .get(&WallpaperId::new("sunset.mp4"))
+                .unwrap()
+                .thumbnail,
+            library
+                .get(&WallpaperId::new("sunset.webm"))
+                .unwrap()
+                .thumbnail
+        );
+    }
+
+    #[test]
+    fn a_thumbnail_resolves_to_the_wallpaper_and_its_rescaled_copy() {
+        let library = library(&["nature/forest.mp4"]);
+        let wallpaper = library
+            .resolve(Path::new(
+                "/cache/wallpapers-thumbnail/nature/forest.mp4.jpg",
+            ))
+            .unwrap();
+        assert_eq!(wallpaper.source, Path::new("/walls/nature/forest.mp4"));
+        assert_eq!(
+            wallpaper.rescaled,
+            Path::new("/cache/wallpapers-rescaled/nature/forest.mp4")
+        );
+    }
+
+    #[test]
+    fn find_takes_ids_and_paths() {
+        let library = library(&["sunset.mp4"]);
+        for path in [
+            "sunset.mp4",
+            "/walls/sunset.mp4",
+            "/cache/wallpapers-rescaled/sunset.mp4",
+            "/cache/wallpapers-thumbnail/sunset.mp4.jpg",
+        ] {
+            assert!(library.find(Path::new(path)).is_some(), "{}", path);
+        }
+        assert!(library.find(Path::new("sunset.webm")).is_none());
+        assert!(library.find(Path::new("/elsewhere/sunset.mp4")).is_none());
+    }
+}
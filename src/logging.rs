@@ -0,0 +1,157 @@
+use std::fs::{rename, File, OpenOptions};
+use std::io::{self, stderr, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// How many rotated log files are kept, as `<log_file>.1` to `<log_file>.N`.
+const ROTATED_LOG_FILES: usize = 3;
+
+static LOG_FILE: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+/// Appends to a file, and moves it aside once it grows past `max_size`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    /// Shifts `<log_file>.1` to `.2` and so on, dropping the oldest, then starts
+    /// a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..ROTATED_LOG_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        rename(&self.path, self.rotated_path(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Writes messages for people on stderr, and with a timestamp to the log file
+/// once there is one.
+struct Logger {
+    filter: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = record.args();
+        let _ = match record.level() {
+            Level::Info => writeln!(stderr(), "{}", message),
+            Level::Warn => writeln!(stderr(), "Warning: {}", message),
+            Level::Error => writeln!(stderr(), "Error: {}", message),
+            Level::Debug | Level::Trace => {
+                writeln!(
+                    stderr(),
+                    "[{}] {}: {}",
+                    record.level(),
+                    record.target(),
+                    message
+                )
+            }
+        };
+
+        if let Some(file) = LOG_FILE.get() {
+            let line = format!(
+                "{} {:<5} {}: {}\n",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                record.level(),
+                record.target(),
+                message
+            );
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            // Nowhere left to report it but stderr
+            if let Err(e) = file.write_line(&line) {
+                let _ = writeln!(
+                    stderr(),
+                    "Error: Could not write to {}: {}",
+                    file.path.to_string_lossy(),
+                    e
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = stderr().flush();
+        if let Some(file) = LOG_FILE.get() {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+/// Logs messages of `level` and above. Other crates only go as far as info, and
+/// `RUST_LOG` can change both, unless `verbosity_given`, as flags win over the
+/// environment.
+pub fn init(level: LevelFilter, verbosity_given: bool) {
+    let apply = |builder: &mut env_logger::Builder| {
+        builder
+            .filter_level(level.min(LevelFilter::Info))
+            .filter_module(env!("CARGO_CRATE_NAME"), level);
+    };
+    let mut builder = env_logger::Builder::new();
+    apply(&mut builder);
+    builder.parse_default_env();
+    if verbosity_given {
+        apply(&mut builder);
+    }
+    let filter = builder.build();
+    log::set_max_level(filter.filter());
+    // Only fails if called twice
+    let _ = log::set_boxed_logger(Box::new(Logger { filter }));
+}
+
+/// Also writes every message to `path`, rotating it once it grows past
+/// `max_size` bytes.
+pub fn log_to_file(path: &Path, max_size: u64) -> Result<(), String> {
+    let file = RotatingFile::open(path, max_size)
+        .map_err(|e| format!("Could not open log file {}: {}", path.to_string_lossy(), e))?;
+    LOG_FILE
+        .set(Mutex::new(file))
+        .map_err(|_| "Already logging to a file".to_string())
+}
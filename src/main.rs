@@ -1,17 +1,49 @@
-use std::fs::{read_dir, remove_file};
+use std::ffi::OsStr;
+use std::io::ErrorKind;
 use std::path::PathBuf;
-use std::{collections::hash_map::HashMap, path::Path};
-
-use clap::{Parser, Subcommand};
-use config::{read_config, Config, ConfigResolution};
-
-use crate::ffmpeg::{generate_thumbnail, get_resolution, rescale_video};
-
+use std::sync::Arc;
+use std::{collections::HashSet, path::Path};
+
+use clap::{ArgAction, Parser, Subcommand};
+use config::{read_config, resolve_config_path, Config, ConfigFile, ConfigResolution};
+use log::{debug, error, info, warn, LevelFilter};
+
+use crate::cache::CacheUsage;
+use crate::control::protocol::{Action, Status};
+use crate::control::{Client, Controller};
+use crate::ffmpeg::{
+    generate_thumbnail, get_duration, get_resolution, rescale_video, EncodeProgress,
+};
+use crate::hooks::RunningHooks;
+use crate::library::{Library, Wallpaper, WallpaperId, IGNORE_FILE_NAME};
+use crate::output::{CacheKind, Message, OutputFormat};
+use crate::progress::BatchProgress;
+use crate::runner::Runner;
+use crate::utils::{path_to_key, walk_files};
+use crate::watch::{Changes, Watcher};
+
+mod cache;
 mod config;
+mod control;
 mod ffmpeg;
+mod history;
+mod hooks;
+mod init;
+mod library;
+mod logging;
 mod mpv;
+mod output;
+mod palette;
+mod progress;
+mod ratings;
+mod renderer;
+mod runner;
+mod scheduler;
+mod solar;
+mod supervisor;
 mod sxiv;
 mod utils;
+mod watch;
 
 trait PrintableError<T> {
     fn print_and_exit(self) -> T;
@@ -20,183 +52,622 @@ trait PrintableError<T> {
 impl<T> PrintableError<T> for Result<T, String> {
     fn print_and_exit(self) -> T {
         self.unwrap_or_else(|s| {
-            println!("{}", s);
+            error!("{}", s);
+            output::event(Message::Error { message: s });
             std::process::exit(-1);
         })
     }
 }
 
-fn try_generate_rescaled_wallpaper(
-    config_resolution: &ConfigResolution,
-    file_path: &Path,
-    file_name: &str,
-    rescaled_path: &Path,
-) {
-    let (width, height) = get_resolution(file_path).print_and_exit();
-    if width == config_resolution.width && height == config_resolution.height {
-        return;
+struct PendingRescale {
+    wallpaper: Wallpaper,
+    duration: Option<f64>,
+}
+
+fn needs_rescaled_wallpaper(
+    runner: &Runner,
+    config: &Config,
+    wallpaper: &Wallpaper,
+) -> Result<bool, String> {
+    let (width, height) = get_resolution(runner, &config.programs.ffprobe, &wallpaper.source)?;
+    if width == config.resolution.width && height == config.resolution.height {
+        return Ok(false);
     }
-    if rescaled_path.is_file() {
-        return;
+    if wallpaper.rescaled.is_file() {
+        return Ok(false);
     }
-    println!(
-        "Resolution {}x{} does not match for {}. Generating rescaled version...",
-        width, height, file_name,
+    info!(
+        "Resolution {}x{} does not match for {}. A rescaled version will be generated.",
+        width, height, wallpaper.id,
     );
-    rescale_video(
-        file_path,
-        config_resolution.width,
-        config_resolution.height,
-        rescaled_path,
-    )
-    .print_and_exit();
-    println!("Rescaled version generated!");
+    Ok(true)
+}
+
+/// Rescales one wallpaper. A copy left half-written by a failure is removed, so
+/// it's generated again next time.
+fn rescale_wallpaper(
+    runner: &Runner,
+    config: &Config,
+    wallpaper: &Wallpaper,
+    on_progress: impl FnMut(&EncodeProgress),
+) -> Result<(), String> {
+    if let Some(parent) = wallpaper.rescaled.parent() {
+        runner.create_dir_all(parent).map_err(|e| {
+            format!(
+                "Failed to create rescaled wallpapers directory {}: {}",
+                parent.to_string_lossy(),
+                e
+            )
+        })?;
+    }
+    let result = rescale_video(
+        runner,
+        &config.programs.ffmpeg,
+        &wallpaper.source,
+        config.resolution.width,
+        config.resolution.height,
+        &wallpaper.rescaled,
+        on_progress,
+    );
+    if result.is_err() && wallpaper.rescaled.is_file() {
+        let _ = runner.remove_file(&wallpaper.rescaled);
+    }
+    result
+}
+
+/// Generates the rescaled copies. Returns how many failed, each of which is
+/// reported and skipped.
+fn generate_rescaled_wallpapers(
+    runner: &Runner,
+    config: &Config,
+    pending: &[PendingRescale],
+) -> usize {
+    let mut failed = 0;
+    if runner.is_dry_run() {
+        for item in pending {
+            // Still goes through the runner, so --print-commands shows the ffmpeg call
+            if let Err(e) = rescale_wallpaper(runner, config, &item.wallpaper, |_| {}) {
+                report_cache_failed(&item.wallpaper, CacheKind::Rescaled, &e);
+                report_skipped(item.wallpaper.id.as_path(), e);
+                failed += 1;
+                continue;
+            }
+            info!("Would generate rescaled version of {}", item.wallpaper.id);
+            report_cached(
+                runner,
+                &item.wallpaper,
+                CacheKind::Rescaled,
+                &item.wallpaper.rescaled,
+            );
+        }
+        return failed;
+    }
+
+    let total_duration = pending.iter().filter_map(|item| item.duration).sum();
+    let mut progress = BatchProgress::new(total_duration);
+    for item in pending {
+        let name = item.wallpaper.id.to_string();
+        info!("Generating rescaled version of {}...", name);
+        progress.start_file(&name, item.duration);
+        let result = rescale_wallpaper(runner, config, &item.wallpaper, |update| {
+            progress.update(update)
+        });
+        progress.finish_file();
+        if let Err(e) = result {
+            report_cache_failed(&item.wallpaper, CacheKind::Rescaled, &e);
+            report_skipped(item.wallpaper.id.as_path(), e);
+            failed += 1;
+            continue;
+        }
+        info!("Rescaled version generated!");
+        report_cached(
+            runner,
+            &item.wallpaper,
+            CacheKind::Rescaled,
+            &item.wallpaper.rescaled,
+        );
+    }
+    failed
 }
 
 fn try_generate_thumbnail_for_wallpaper(
-    file_path: &Path,
-    file_name: &str,
-    thumbnail_path: &Path,
-) -> bool {
-    if thumbnail_path.exists() {
-        println!("Thumbnail for file {} already exists", file_name);
-        return false;
+    runner: &Runner,
+    config: &Config,
+    wallpaper: &Wallpaper,
+) -> Result<bool, String> {
+    if wallpaper.thumbnail.exists() {
+        debug!("Thumbnail for file {} already exists", wallpaper.id);
+        return Ok(false);
     }
 
-    println!("Missing thumbnail for file {}. Generating...", file_name,);
-    generate_thumbnail(file_path, thumbnail_path).print_and_exit();
-    true
+    if runner.is_dry_run() {
+        info!(
+            "Missing thumbnail for file {}. Would generate it.",
+            wallpaper.id
+        );
+    } else {
+        info!("Missing thumbnail for file {}. Generating...", wallpaper.id);
+    }
+    if let Some(parent) = wallpaper.thumbnail.parent() {
+        runner.create_dir_all(parent).map_err(|e| {
+            format!(
+                "Failed to create thumbnails directory {}: {}",
+                parent.to_string_lossy(),
+                e
+            )
+        })?;
+    }
+    generate_thumbnail(
+        runner,
+        &config.programs.ffmpeg,
+        &wallpaper.source,
+        &wallpaper.thumbnail,
+    )
+    .inspect_err(|e| report_cache_failed(wallpaper, CacheKind::Thumbnail, e))?;
+    report_cached(
+        runner,
+        wallpaper,
+        CacheKind::Thumbnail,
+        &wallpaper.thumbnail,
+    );
+    Ok(true)
+}
+
+/// Tells scripts a file was added to the cache, or would be in a dry run.
+fn report_cached(runner: &Runner, wallpaper: &Wallpaper, kind: CacheKind, path: &Path) {
+    output::event(Message::Cached {
+        wallpaper: wallpaper.id.as_path().to_path_buf(),
+        kind,
+        path: path.to_path_buf(),
+        dry_run: runner.is_dry_run(),
+    });
 }
 
-fn generate_cache(config: &Config) {
-    let mut cached_filenames = HashMap::new();
-    let mut rescaled_wallpapers = HashMap::new();
+/// Tells scripts a file could not be added to the cache. The wallpaper is then
+/// reported as skipped.
+fn report_cache_failed(wallpaper: &Wallpaper, kind: CacheKind, reason: &str) {
+    output::event(Message::CacheFailed {
+        wallpaper: wallpaper.id.as_path().to_path_buf(),
+        kind,
+        reason: reason.to_string(),
+    });
+}
 
-    println!("Listing wallpapers at {:?}", config.wallpapers_dir);
-    for dir_item in read_dir(&config.wallpapers_dir).expect("Could not read wallpapers directory.")
-    {
-        let dir_item = dir_item.expect("Failed to unwrap directory");
-        // Skip non-files
-        {
-            let file_type = dir_item
-                .file_type()
-                .expect("Failed to get filetype from dir_item");
-
-            if !file_type.is_file() {
-                continue;
-            };
+/// Tells that a wallpaper is left out of the cache, and why.
+fn report_skipped(wallpaper: &Path, reason: String) {
+    warn!("Skipping {}: {}", wallpaper.to_string_lossy(), reason);
+    output::event(Message::Skipped {
+        wallpaper: wallpaper.to_path_buf(),
+        reason,
+    });
+}
+
+/// Generates what's missing from the cache for one wallpaper. Its rescaled copy
+/// is returned to be generated along with the others.
+fn cache_wallpaper(
+    runner: &Runner,
+    config: &Config,
+    cache_usage: &CacheUsage,
+    wallpaper: &Wallpaper,
+) -> Result<Option<PendingRescale>, String> {
+    try_generate_thumbnail_for_wallpaper(runner, config, wallpaper)?;
+    if config.generate_palettes && !wallpaper.palette.is_file() {
+        if runner.is_dry_run() {
+            info!(
+                "Missing palette for file {}. Would extract it.",
+                wallpaper.id
+            );
+        } else {
+            info!("Missing palette for file {}. Extracting...", wallpaper.id);
+            palette::palette_for(runner, config, wallpaper, false)
+                .inspect_err(|e| report_cache_failed(wallpaper, CacheKind::Palette, e))?;
         }
+        report_cached(runner, wallpaper, CacheKind::Palette, &wallpaper.palette);
+    }
+    if cache_usage.is_evicted(&wallpaper.rescaled) {
+        info!(
+            "Rescaled version of {} was evicted from the cache. It will be generated when played.",
+            wallpaper.id
+        );
+    } else if needs_rescaled_wallpaper(runner, config, wallpaper)? {
+        return Ok(Some(PendingRescale {
+            duration: get_duration(runner, &config.programs.ffprobe, &wallpaper.source)?,
+            wallpaper: wallpaper.clone(),
+        }));
+    }
+    Ok(None)
+}
 
-        let file_path = dir_item.path();
-        let file_stem = file_path
-            .file_stem()
-            .expect("Could not extract file stem.")
-            .to_string_lossy();
-        let file_name = file_path
-            .file_name()
-            .expect("Could not extract file name.")
-            .to_string_lossy();
-
-        let thumbnail_path = config
-            .thumbnails_cache_dir
-            .join(format!("{}.jpg", file_stem));
-        let rescaled_path = config.wallpapers_rescaled_dir.join(file_name.to_string());
-
-        try_generate_thumbnail_for_wallpaper(&file_path, &file_name, &thumbnail_path);
-        try_generate_rescaled_wallpaper(&config.resolution, &file_path, &file_name, &rescaled_path);
-
-        cached_filenames.insert(thumbnail_path, true);
-        rescaled_wallpapers.insert(rescaled_path, true);
-    }
-
-    let remove_unused_cache_files =
-        |path: &Path, dict: &HashMap<PathBuf, bool>, remove_message_maker: fn(path: &PathBuf)| {
-            for dir_item in read_dir(path).expect("Could not read cache directory") {
-                let dir_item = dir_item.expect("Failed to unwrap directory");
-                // Skip non-files
-                {
-                    let file_type = dir_item
-                        .file_type()
-                        .expect("Failed to get filetype from dir_item");
-
-                    if !file_type.is_file() {
-                        continue;
-                    };
-                }
+fn report_not_media(path: &Path) {
+    report_skipped(
+        path,
+        format!(
+            "not an allowed video or image. List it in {} to silence this.",
+            IGNORE_FILE_NAME
+        ),
+    );
+}
 
-                let file_path = dir_item.path();
+/// Removes the thumbnails, rescaled wallpapers and palettes of wallpapers that
+/// are not in the library anymore.
+fn remove_unused_cache_files(config: &Config, runner: &Runner, library: &Library) {
+    let mut cached_filenames = HashSet::new();
+    let mut rescaled_wallpapers = HashSet::new();
+    let mut palettes = HashSet::new();
+    for wallpaper in library.wallpapers() {
+        cached_filenames.insert(wallpaper.thumbnail.clone());
+        rescaled_wallpapers.insert(wallpaper.rescaled.clone());
+        palettes.insert(wallpaper.palette.clone());
+    }
 
-                // There is a wallpaper for this cache item
-                if dict.contains_key(&file_path) {
-                    continue;
+    let remove_unused = |path: &Path, known: &HashSet<PathBuf>, kind: CacheKind, name: &str| {
+        let files = match walk_files(path) {
+            Ok(files) => files,
+            // Nothing was cached there yet
+            Err(e) if e.kind() == ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!(
+                    "Could not read cache directory {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                return;
+            }
+        };
+        for file_path in files {
+            // There is a wallpaper for this cache item
+            if known.contains(&file_path) {
+                continue;
+            }
+
+            info!(
+                "{} named {} has no wallpaper. {}",
+                name,
+                file_path.to_string_lossy(),
+                if runner.is_dry_run() {
+                    "Would remove it."
+                } else {
+                    "Removing it."
                 }
+            );
+
+            if let Err(e) = runner.remove_file(&file_path) {
+                warn!("Could not remove {}: {}", file_path.to_string_lossy(), e);
+                continue;
+            }
+            output::event(Message::CacheRemoved {
+                kind,
+                path: file_path,
+                dry_run: runner.is_dry_run(),
+            });
+        }
+    };
+    remove_unused(
+        &config.thumbnails_cache_dir,
+        &cached_filenames,
+        CacheKind::Thumbnail,
+        "Thumbnail",
+    );
+    remove_unused(
+        &config.wallpapers_rescaled_dir,
+        &rescaled_wallpapers,
+        CacheKind::Rescaled,
+        "Rescaled wallpaper",
+    );
+    remove_unused(
+        &config.palettes_cache_dir,
+        &palettes,
+        CacheKind::Palette,
+        "Palette",
+    );
+}
+
+/// Tells scripts the cache is up to date, but for the `failed` wallpapers.
+fn report_synced(library: &Library, failed: usize) {
+    output::event(Message::CacheSynced {
+        wallpapers: library.wallpapers().count(),
+        skipped: library.skipped().len(),
+        failed,
+    });
+}
+
+/// Brings the whole cache up to date. Returns how many wallpapers failed, each
+/// of which is reported and skipped.
+fn generate_cache(config: &Config, runner: &Runner) -> Result<usize, String> {
+    let mut pending_rescales = Vec::new();
+    let cache_usage = CacheUsage::read(&config.cache_dir);
+
+    info!("Listing wallpapers at {:?}", config.wallpapers_dir);
+    let library = Library::scan(config)?;
+    for path in library.skipped() {
+        report_not_media(path);
+    }
+    let mut failed = 0;
+    for wallpaper in library.wallpapers() {
+        match cache_wallpaper(runner, config, &cache_usage, wallpaper) {
+            Ok(Some(pending)) => pending_rescales.push(pending),
+            Ok(None) => {}
+            Err(e) => {
+                report_skipped(wallpaper.id.as_path(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    failed += generate_rescaled_wallpapers(runner, config, &pending_rescales);
+    remove_unused_cache_files(config, runner, &library);
+    report_synced(&library, failed);
 
-                remove_message_maker(&file_path);
+    cache::warn_if_over_budget(config);
+    Ok(failed)
+}
+
+/// Brings the cache up to date with the files that changed, once they're
+/// written.
+fn update_cache(config: &Config, runner: &Runner, changes: &Changes) {
+    let touches_ignore_file = changes
+        .changed
+        .iter()
+        .chain(&changes.removed)
+        .any(|path| path.file_name() == Some(OsStr::new(IGNORE_FILE_NAME)));
+    // Any wallpaper may have come in or out of the library
+    if changes.overflowed || touches_ignore_file {
+        if let Err(e) = generate_cache(config, runner) {
+            error!("{}", e);
+        }
+        return;
+    }
 
-                remove_file(&file_path).expect("Failed to remove cached item");
+    let library = match Library::scan(config) {
+        Ok(library) => library,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let cache_usage = CacheUsage::read(&config.cache_dir);
+    let mut pending_rescales = Vec::new();
+    let mut failed = 0;
+    for path in &changes.changed {
+        let Some(wallpaper) = library.get(&WallpaperId::new(path)) else {
+            if library.skipped().contains(path) {
+                report_not_media(path);
             }
+            continue;
         };
-    remove_unused_cache_files(&config.thumbnails_cache_dir, &cached_filenames, |path| {
-        println!(
-            "Thumbnail named {} has no wallpaper. Removing it.",
-            path.to_string_lossy()
+        info!("{} was added or modified", wallpaper.id);
+        // Whatever was cached came from the previous version of the file
+        for cached in [
+            &wallpaper.thumbnail,
+            &wallpaper.rescaled,
+            &wallpaper.palette,
+        ] {
+            if cached.is_file() {
+                if let Err(e) = runner.remove_file(cached) {
+                    warn!("Could not remove {}: {}", cached.to_string_lossy(), e);
+                }
+            }
+        }
+        match cache_wallpaper(runner, config, &cache_usage, wallpaper) {
+            Ok(Some(pending)) => pending_rescales.push(pending),
+            Ok(None) => {}
+            Err(e) => {
+                report_skipped(wallpaper.id.as_path(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed += generate_rescaled_wallpapers(runner, config, &pending_rescales);
+
+    if !changes.removed.is_empty() {
+        remove_unused_cache_files(config, runner, &library);
+    }
+    report_synced(&library, failed);
+    cache::warn_if_over_budget(config);
+}
+
+/// Generates the cache, then keeps it up to date as wallpapers are added,
+/// modified and removed. Only returns on failure.
+fn watch_cache(config: &Config, runner: &Runner) -> Result<(), String> {
+    // Started first, so nothing changed during the first pass goes unnoticed
+    let mut watcher = Watcher::new(&config.wallpapers_dir).map_err(|e| {
+        format!(
+            "Could not watch {}: {}",
+            config.wallpapers_dir.to_string_lossy(),
+            e
         )
-    });
-    remove_unused_cache_files(
-        &config.wallpapers_rescaled_dir,
-        &rescaled_wallpapers,
-        |path| {
-            println!(
-                "Rescaled wallpaper named {} has no wallpaper. Removing it.",
-                path.to_string_lossy()
-            )
-        },
+    })?;
+    generate_cache(config, runner)?;
+    info!(
+        "Watching {} for changes",
+        config.wallpapers_dir.to_string_lossy()
     );
+    loop {
+        let changes = watcher
+            .next()
+            .map_err(|e| format!("Failed to watch the wallpapers: {}", e))?;
+        update_cache(config, runner, &changes);
+    }
 }
 
-fn select_wallpaper(config: &Config, socket_path: &Path, is_static: bool) {
-    println!("{}", config.thumbnails_cache_dir.to_string_lossy());
-    let selected_path = sxiv::ask_user_input_single(if is_static {
-        &config.wallpapers_dir
-    } else {
-        &config.thumbnails_cache_dir
-    })
-    .print_and_exit();
+fn run_daemon(args: &Args, config: Config) -> anyhow::Result<()> {
+    if let Some(log_file) = &config.log_file {
+        logging::log_to_file(log_file, config.log_max_size).map_err(anyhow::Error::msg)?;
+    }
+    let controller = Arc::new(Controller::new(args.clone(), config.clone()));
+    control::listen(Arc::clone(&controller), &config.control_socket_path)?;
+    // Runs even without a schedule, as reloading the configuration may add one
+    std::thread::spawn(move || scheduler::run(&controller));
+
+    let result = supervisor::run(&config);
+    let _ = std::fs::remove_file(&config.control_socket_path);
+    result
+}
 
-    let selected_file_name = selected_path
-        .file_name()
-        .ok_or_else(|| "Selected wallpaper has no name".to_string())
-        .print_and_exit();
+fn print_status(status: Status) {
+    let human = match &status.wallpaper {
+        Some(wallpaper) => format!(
+            "Playing \"{}\"{}",
+            wallpaper,
+            if status.paused { " (paused)" } else { "" }
+        ),
+        None => "Nothing was played through the daemon yet".to_string(),
+    };
+    output::result(Message::Status(status), || println!("{}", human));
+}
+
+/// The status of a wallpaper played without the daemon.
+fn status_of(wallpaper: &Wallpaper) -> Status {
+    Status {
+        wallpaper: Some(path_to_key(wallpaper.id.as_path())),
+        path: Some(path_to_key(&wallpaper.source)),
+        paused: false,
+    }
+}
 
-    println!("Selected \"{}\"", selected_file_name.to_string_lossy());
-    let selected_file_stem = selected_path
-        .file_stem()
-        .expect("Selected wallpaper has no stem");
-
-    let find_wallpaper_path = |dir: &Path| -> Option<PathBuf> {
-        for dir_entry in read_dir(dir).expect("Failed to read wallpapers dir") {
-            let dir_entry_path = dir_entry
-                .expect("Wallpaper dir entry failed for an unexpected reason.")
-                .path();
-            let dir_entry_stem = dir_entry_path
-                .file_stem()
-                .expect("Failed to get wallpaper dir entry file stem");
-            if dir_entry_stem == selected_file_stem {
-                return Some(dir_entry_path);
+/// Sends a command to the daemon, and prints what it answers.
+fn control_command(config: &Config, action: Action) -> Result<(), String> {
+    match action {
+        Action::Status => {
+            let status = Client::connect_or_fail(config)?.status()?;
+            print_status(status);
+        }
+        Action::Subscribe => Client::connect_or_fail(config)?.subscribe(|event| {
+            let human = match &event.status.wallpaper {
+                Some(wallpaper) => {
+                    format!("{} \"{}\"", event.event.name(), wallpaper)
+                }
+                None => event.event.name().to_string(),
             };
+            output::result(Message::Event(event), || println!("{}", human));
+        })?,
+        Action::ReloadConfig => {
+            control::send(config, Action::ReloadConfig)?;
+            output::event(Message::ConfigReloaded);
         }
-        None
+        action => {
+            let mut client = Client::connect_or_fail(config)?;
+            client.send(action)?;
+            if output::is_json() {
+                output::event(Message::Status(client.status()?));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generates a wallpaper's rescaled copy again if it's missing, like after it was
+/// evicted from the cache. Returns whether it did. If that fails, the original
+/// is played instead.
+fn restore_rescaled(runner: &Runner, config: &Config, wallpaper: &Wallpaper) -> bool {
+    let restore = || -> Result<bool, String> {
+        if !needs_rescaled_wallpaper(runner, config, wallpaper)? {
+            return Ok(false);
+        }
+        let duration = get_duration(runner, &config.programs.ffprobe, &wallpaper.source)?;
+        let pending = PendingRescale {
+            wallpaper: wallpaper.clone(),
+            duration,
+        };
+        Ok(generate_rescaled_wallpapers(runner, config, &[pending]) == 0)
     };
+    restore().unwrap_or_else(|e| {
+        warn!("{}", e);
+        false
+    })
+}
 
-    let path = find_wallpaper_path(&config.wallpapers_rescaled_dir)
-        .or_else(|| find_wallpaper_path(&config.wallpapers_dir))
-        .expect("Could not find corresponding wallpaper");
+/// Shows a wallpaper and starts the `on_change` hooks. Every wallpaper played,
+/// by the daemon or a command, goes through here.
+fn show_wallpaper(config: &Config, wallpaper: &Wallpaper) -> std::io::Result<RunningHooks> {
+    let runner = Runner::default();
+    let restored = restore_rescaled(&runner, config, wallpaper);
+    let hooks = renderer::show(config, wallpaper)?;
+    // Once it's played, so it's the last copy to go
+    if restored && config.max_cache_size.is_some() {
+        if let Err(e) = cache::prune(config, &runner) {
+            warn!("{}", e);
+        }
+    }
+    Ok(hooks)
+}
+
+/// Plays a wallpaper through the daemon when it's running, so it knows what's
+/// playing, or else directly. The hooks started when playing it directly are
+/// returned, to wait for them before exiting.
+fn play(config: &Config, wallpaper: &Wallpaper) -> Result<Option<RunningHooks>, String> {
+    if let Some(mut client) = Client::connect(config) {
+        client.send(Action::Set {
+            wallpaper: path_to_key(wallpaper.id.as_path()),
+        })?;
+        return Ok(None);
+    }
+    let hooks = show_wallpaper(config, wallpaper)
+        .map_err(|e| format!("Failed to show the wallpaper: {}", e))?;
+    history::record(config, wallpaper);
+    Ok(Some(hooks))
+}
+
+/// Plays a wallpaper picked at random. The daemon picks it when it's running.
+fn play_random(config: &Config, tag: Option<&str>, folder: Option<&Path>) -> Result<(), String> {
+    if let Some(mut client) = Client::connect(config) {
+        client.send(Action::Random {
+            tag: tag.map(str::to_string),
+            folder: folder.map(path_to_key),
+        })?;
+        let status = client.status()?;
+        if status.wallpaper.is_some() {
+            print_status(status);
+        }
+        return Ok(());
+    }
+
+    let library = Library::scan(config)?;
+    let candidates = library.filter(config, tag, folder)?;
+    let current = library.last_played(config).map(|wallpaper| &wallpaper.id);
+    let wallpaper =
+        ratings::pick_weighted(config, &candidates, current, config.random_avoid_recent)
+            .ok_or("There are no wallpapers to pick from")?;
+    print_status(status_of(wallpaper));
+    if let Some(hooks) = play(config, wallpaper)? {
+        hooks.wait();
+    }
+    Ok(())
+}
+
+fn select_wallpaper(config: &Config, is_static: bool) {
+    let runner = Runner::default();
+    let dir = if is_static {
+        &config.wallpapers_dir
+    } else {
+        &config.thumbnails_cache_dir
+    };
+    debug!("Picking a wallpaper from {}", dir.to_string_lossy());
+    let selected_path =
+        sxiv::ask_user_input_single(&runner, &config.programs.sxiv, dir).print_and_exit();
+
+    let library = Library::scan(config).print_and_exit();
+    let wallpaper = library
+        .resolve(&selected_path)
+        .ok_or_else(|| {
+            format!(
+                "Could not find the wallpaper for {}",
+                selected_path.to_string_lossy()
+            )
+        })
+        .print_and_exit();
+
+    output::result(Message::Status(status_of(wallpaper)), || {
+        println!("Selected \"{}\"", wallpaper.id)
+    });
 
-    mpv::load_file(socket_path, &path)
+    if let Some(hooks) = play(config, wallpaper).print_and_exit() {
+        hooks.wait();
+    }
 }
 
 /// Program to manage my personal wallpapers
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// The directory to look for the wallpapers
@@ -211,22 +682,163 @@ struct Args {
     #[arg(short, long)]
     config_dir: Option<PathBuf>,
 
+    /// The profile to use, out of the ones defined in the configuration
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// Print more details. Repeat for even more
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only print warnings and errors. Repeat to only print errors
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    quiet: u8,
+
+    /// How results are printed on stdout. With `json`, every command prints one
+    /// JSON object per line
+    #[arg(long, value_enum, global = true, default_value_t)]
+    output: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
+enum ScheduleCommands {
+    /// Print today's sunrise and sunset, and when each schedule entry starts
+    Show {},
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum CacheCommands {
+    /// Show how much space each cache directory uses
+    Stats {},
+    /// Evict the least recently used rescaled wallpapers until the cache fits in max_cache_size
+    Prune {
+        /// Report what would be evicted, without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
 enum Commands {
+    /// Create the configuration file. Asks for each setting, unless --yes is given
+    Init {
+        /// Don't ask anything. Use the values given as arguments, or else the detected defaults
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Overwrite the configuration file if it already exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// The screen resolution, like 1920x1080. Detected through xrandr if not provided
+        #[arg(short, long)]
+        resolution: Option<ConfigResolution>,
+    },
+    /// Upgrade the configuration file to the current layout, keeping a backup of the original
+    MigrateConfig {},
     Daemon {
         // The path to the MPV socket. Defaults to /tmp/wallpaper-mpv-socket
-        #[arg(short, long, default_value = "/tmp/wallpaper-mpv-socket")]
-        socket_path: PathBuf,
+        #[arg(short, long)]
+        socket_path: Option<PathBuf>,
+    },
+    GenerateCache {
+        /// Report what would be generated and removed, without touching the cache
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Print every ffmpeg and ffprobe invocation
+        #[arg(long)]
+        print_commands: bool,
+
+        /// Keep running, and update the cache as wallpapers are added, modified and
+        /// removed
+        #[arg(long)]
+        watch: bool,
+    },
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommands,
+    },
+    /// Print the colour palette of a wallpaper, and export it as pywal's colors.json, colors.Xresources and colors.sh
+    Palette {
+        /// The wallpaper, relative to the wallpapers directory. Defaults to the one played last
+        wallpaper: Option<PathBuf>,
+
+        /// Where to write the theme files. Defaults to wallpaper-manager-theme in the cache directory
+        #[arg(long)]
+        export_dir: Option<PathBuf>,
+
+        /// Extract the palette again, even if it is cached
+        #[arg(long)]
+        regenerate: bool,
+    },
+    /// Play a wallpaper, relative to the wallpapers directory, through the daemon
+    Set { wallpaper: PathBuf },
+    /// Go forward again after `previous`, or else play the next wallpaper in the order of their paths, through the daemon
+    Next {
+        /// Step through the wallpapers of this tag instead
+        #[arg(short, long)]
+        tag: Option<String>,
+    },
+    /// Go back to the wallpaper shown before, through the daemon
+    Previous {
+        /// Play the previous wallpaper of this tag instead of going back in the history
+        #[arg(short, long)]
+        tag: Option<String>,
+    },
+    /// Play a wallpaper picked at random, favouring the best rated ones and avoiding the ones shown last
+    Random {
+        /// Pick out of the wallpapers of this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+
+        /// Pick out of the wallpapers inside this folder, relative to the wallpapers directory
+        #[arg(short, long)]
+        folder: Option<PathBuf>,
+    },
+    /// Rate a wallpaper from 0 to 5. Better rated wallpapers are picked at random more often, and 0 means never
+    Rate {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=ratings::MAX_RATING as i64))]
+        rating: u8,
+
+        /// The wallpaper, relative to the wallpapers directory. Defaults to the one playing
+        wallpaper: Option<PathBuf>,
+    },
+    /// Mark a wallpaper as a favourite, which is picked at random more often
+    #[command(visible_alias = "favourite")]
+    Favorite {
+        /// The wallpaper, relative to the wallpapers directory. Defaults to the one playing
+        wallpaper: Option<PathBuf>,
+
+        /// Unmark it instead
+        #[arg(short, long)]
+        remove: bool,
+    },
+    /// Pause the wallpaper if it's playing, or resume it
+    Pause {},
+    /// Print what the daemon is playing
+    Status {},
+    /// Make the daemon read the configuration again
+    ReloadConfig {},
+    /// Print every change the daemon makes, as it happens
+    Subscribe {},
+    /// List the wallpapers shown last, most recent first
+    History {
+        /// How many to list
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
     },
-    GenerateCache {},
     SelectWallpaper {
         // The path to the MPV socket. Defaults to /tmp/wallpaper-mpv-socket
-        #[arg(short, long, default_value = "/tmp/wallpaper-mpv-socket")]
-        socket_path: PathBuf,
+        #[arg(short, long)]
+        socket_path: Option<PathBuf>,
 
         // If provided, will atempt to load the result as a static image.
         #[arg(short = 'c', long = "static")]
@@ -234,18 +846,139 @@ enum Commands {
     },
 }
 
+impl Args {
+    /// The level set by `--verbose` and `--quiet`, if any of them is given.
+    fn log_level(&self) -> Option<LevelFilter> {
+        match (self.verbose, self.quiet) {
+            (0, 0) => None,
+            (0, 1) => Some(LevelFilter::Warn),
+            (0, _) => Some(LevelFilter::Error),
+            (1, _) => Some(LevelFilter::Debug),
+            (_, _) => Some(LevelFilter::Trace),
+        }
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let log_level = args.log_level();
+    logging::init(log_level.unwrap_or(LevelFilter::Info), log_level.is_some());
+    output::init(args.output);
+
+    let result = run(&args);
+    if let Err(e) = &result {
+        output::event(Message::Error {
+            message: e.to_string(),
+        });
+    }
+    result
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    if let Commands::Init {
+        yes,
+        force,
+        resolution,
+    } = &args.command
+    {
+        return init::run(
+            args,
+            &init::InitOptions {
+                yes: *yes,
+                force: *force,
+                resolution: resolution.clone(),
+            },
+        );
+    }
+
+    if let Commands::MigrateConfig {} = &args.command {
+        let config_file_path = resolve_config_path(args)?;
+        let backup = ConfigFile::upgrade_file(&config_file_path)?;
+        let human = match &backup {
+            Some(backup_path) => format!(
+                "Configuration upgraded. The original was kept at {}",
+                backup_path.to_string_lossy()
+            ),
+            None => "Configuration is already up to date".to_string(),
+        };
+        output::result(Message::ConfigMigrated { backup }, || println!("{}", human));
+        return Ok(());
+    }
 
-    let config = read_config(&args)?;
+    let config = read_config(args)?;
 
     match &args.command {
-        Commands::Daemon { socket_path } => mpv::run(socket_path)?,
-        Commands::GenerateCache {} => generate_cache(&config),
-        Commands::SelectWallpaper {
-            socket_path,
-            static_image,
-        } => select_wallpaper(&config, socket_path, *static_image),
+        Commands::Init { .. } | Commands::MigrateConfig {} => {
+            unreachable!("runs before the configuration is read")
+        }
+        Commands::Daemon { .. } => run_daemon(args, config)?,
+        Commands::GenerateCache {
+            dry_run,
+            print_commands,
+            watch,
+        } => {
+            let runner = Runner::new(*dry_run, *print_commands);
+            if *watch {
+                watch_cache(&config, &runner).print_and_exit();
+            } else {
+                let failed = generate_cache(&config, &runner).print_and_exit();
+                if failed > 0 {
+                    Err(format!("{} wallpapers could not be cached", failed)).print_and_exit()
+                }
+            }
+        }
+        Commands::Cache { command } => match command {
+            CacheCommands::Stats {} => cache::print_stats(&config),
+            CacheCommands::Prune { dry_run } => {
+                cache::prune(&config, &Runner::new(*dry_run, false)).print_and_exit()
+            }
+        },
+        Commands::Schedule { command } => match command {
+            ScheduleCommands::Show {} => scheduler::show(&config),
+        },
+        Commands::Palette {
+            wallpaper,
+            export_dir,
+            regenerate,
+        } => palette::run(
+            &config,
+            wallpaper.as_deref(),
+            export_dir.as_deref(),
+            *regenerate,
+        )
+        .print_and_exit(),
+        Commands::Set { wallpaper } => control_command(
+            &config,
+            Action::Set {
+                wallpaper: path_to_key(wallpaper),
+            },
+        )
+        .print_and_exit(),
+        Commands::Next { tag } => {
+            control_command(&config, Action::Next { tag: tag.clone() }).print_and_exit()
+        }
+        Commands::Previous { tag } => {
+            control_command(&config, Action::Previous { tag: tag.clone() }).print_and_exit()
+        }
+        Commands::History { limit } => history::print(&config, *limit),
+        Commands::Random { tag, folder } => {
+            play_random(&config, tag.as_deref(), folder.as_deref()).print_and_exit()
+        }
+        Commands::Rate { rating, wallpaper } => {
+            ratings::rate(&config, wallpaper.as_deref(), *rating).print_and_exit()
+        }
+        Commands::Favorite { wallpaper, remove } => {
+            ratings::set_favourite(&config, wallpaper.as_deref(), !*remove).print_and_exit()
+        }
+        Commands::Pause {} => {
+            control_command(&config, Action::Pause { paused: None }).print_and_exit()
+        }
+        Commands::Status {} => control_command(&config, Action::Status).print_and_exit(),
+        Commands::ReloadConfig {} => {
+            control_command(&config, Action::ReloadConfig).print_and_exit()
+        }
+        Commands::Subscribe {} => control_command(&config, Action::Subscribe).print_and_exit(),
+        Commands::SelectWallpaper { static_image, .. } => select_wallpaper(&config, *static_image),
     };
 
     Ok(())
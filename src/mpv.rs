@@ -1,45 +1,108 @@
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::net::UnixStream;
-use std::os::unix::process::CommandExt;
-use std::process::Command;
-use std::{io::Write, path::Path};
-
-pub fn run(socket_path: &Path) -> anyhow::Result<()> {
-    let exec = Command::new("xwinwrap")
-        .args([
-            "-ov",
-            "-b",
-            "-fs",
-            "-g",
-            "1920x1080+0+0",
-            "--",
-            "mpv",
-            "-wid",
-            "WID",
-            "--idle=",
-            "--no-osc",
-            "--no-osd-bar",
-            "--loop-file",
-            "--player-operation-mode=cplayer",
-            "--no-audio",
-            "--panscan=1.0",
-            "--no-input-default-bindings",
-            &format!("--input-ipc-server={}", socket_path.to_string_lossy()),
-        ])
-        .exec();
-
-    Err(exec)?
-}
-
-pub fn load_file(socket_path: &Path, image_path: &Path) {
-    let mut socket_stream =
-        UnixStream::connect(socket_path).expect("Failed to connect to MPV socket.");
-
-    let payload = format!(
-        "{{ \"command\": [\"loadfile\", \"{}\"] }}\n",
-        image_path.to_string_lossy()
-    );
-
-    socket_stream
-        .write_all(&payload.bytes().collect::<Vec<u8>>())
-        .expect("Failed to write to MPV socket.");
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// The mpv flags every renderer plays wallpapers with.
+pub const DEFAULT_ARGS: [&str; 7] = [
+    "--idle=",
+    "--no-osc",
+    "--no-osd-bar",
+    "--loop-file",
+    "--no-audio",
+    "--panscan=1.0",
+    "--no-input-default-bindings",
+];
+
+/// Whether an mpv instance is listening on the socket.
+pub fn is_running(socket_path: &Path) -> bool {
+    UnixStream::connect(socket_path).is_ok()
+}
+
+/// Waits for mpv to create its socket, up to `timeout`.
+pub fn wait_for_socket(socket_path: &Path, timeout: Duration) -> io::Result<()> {
+    let started = Instant::now();
+    loop {
+        match UnixStream::connect(socket_path) {
+            Ok(_) => return Ok(()),
+            Err(e) if started.elapsed() >= timeout => return Err(e),
+            Err(_) => sleep(Duration::from_millis(200)),
+        }
+    }
+}
+
+fn send_command(socket_path: &Path, arguments: &[&[u8]]) -> io::Result<()> {
+    let mut socket_stream = UnixStream::connect(socket_path)?;
+
+    let mut payload = b"{ \"command\": [".to_vec();
+    for (i, argument) in arguments.iter().enumerate() {
+        if i > 0 {
+            payload.extend(b", ");
+        }
+        payload.extend(json_string(argument));
+    }
+    payload.extend(b"] }\n");
+
+    socket_stream.write_all(&payload)
+}
+
+/// Makes mpv play a file instead of the current one.
+pub fn load_file(socket_path: &Path, path: &Path) -> io::Result<()> {
+    send_command(socket_path, &[b"loadfile", path.as_os_str().as_bytes()])
+}
+
+pub fn set_paused(socket_path: &Path, paused: bool) -> io::Result<()> {
+    let value: &[u8] = if paused { b"yes" } else { b"no" };
+    send_command(socket_path, &[b"set", b"pause", value])
+}
+
+pub fn quit(socket_path: &Path) -> io::Result<()> {
+    send_command(socket_path, &[b"quit"])
+}
+
+/// Builds a JSON string literal out of raw bytes. mpv doesn't require its JSON
+/// strings to be valid UTF-8, so bytes are kept as they are, which lets paths that
+/// are not valid UTF-8 through. Only quotes, backslashes and control characters
+/// are escaped.
+fn json_string(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() + 2);
+    result.push(b'"');
+    for &byte in bytes {
+        match byte {
+            b'"' => result.extend(b"\\\""),
+            b'\\' => result.extend(b"\\\\"),
+            b'\n' => result.extend(b"\\n"),
+            b'\t' => result.extend(b"\\t"),
+            b'\r' => result.extend(b"\\r"),
+            0..=0x1f => result.extend(format!("\\u{:04x}", byte).bytes()),
+            _ => result.push(byte),
+        }
+    }
+    result.push(b'"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escapes_quotes_and_control_characters() {
+        assert_eq!(json_string(br#"say "hi"\"#), br#""say \"hi\"\\""#);
+        assert_eq!(
+            json_string(b"two\nlines\tand\r\x01"),
+            br#""two\nlines\tand\r\u0001""#
+        );
+    }
+
+    #[test]
+    fn json_string_keeps_unicode_and_invalid_bytes() {
+        assert_eq!(
+            json_string("forêt 森.mp4".as_bytes()),
+            "\"forêt 森.mp4\"".as_bytes()
+        );
+        assert_eq!(json_string(b"a\x80\xffb"), b"\"a\x80\xffb\"");
+    }
 }
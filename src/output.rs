@@ -0,0 +1,232 @@
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_derive::Serialize;
+
+use crate::control::protocol::{Event, Status};
+use crate::history::HistoryEntry;
+use crate::palette::Theme;
+use crate::utils::{key_to_path, path_to_key};
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// How results are printed on stdout. Messages about what's going on are logged
+/// on stderr either way.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    /// One JSON object per line, with a `type`
+    Json,
+}
+
+/// What a cache file holds.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheKind {
+    Thumbnail,
+    Rescaled,
+    Palette,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheDirStats {
+    pub kind: CacheKind,
+    #[serde(serialize_with = "key")]
+    pub path: PathBuf,
+    pub files: usize,
+    /// In bytes.
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SolarTime {
+    pub event: &'static str,
+    /// Like `"06:42"`. `None` when it doesn't happen today.
+    pub time: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScheduledWallpaper {
+    /// Like `"18:30"`.
+    pub start: String,
+    /// As written in the configuration, like `"sunset-00:30"`.
+    pub trigger: String,
+    #[serde(serialize_with = "option_key")]
+    pub wallpaper: Option<PathBuf>,
+    pub tag: Option<String>,
+    #[serde(serialize_with = "option_key")]
+    pub folder: Option<PathBuf>,
+    /// Whether it's the entry playing now.
+    pub current: bool,
+}
+
+/// Everything printed on stdout with `--output json`. Wallpapers are given
+/// relative to the wallpapers directory, and paths the way `path_to_key` writes
+/// them. Fields are only ever added, so scripts reading them keep working.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Message {
+    /// A file of the wallpapers directory that isn't cached.
+    Skipped {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        reason: String,
+    },
+    /// A file was added to the cache, or would be with `--dry-run`.
+    Cached {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        kind: CacheKind,
+        #[serde(serialize_with = "key")]
+        path: PathBuf,
+        dry_run: bool,
+    },
+    /// A file could not be added to the cache. The wallpaper is skipped.
+    CacheFailed {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        kind: CacheKind,
+        reason: String,
+    },
+    /// A cache file was removed, or would be with `--dry-run`.
+    CacheRemoved {
+        kind: CacheKind,
+        #[serde(serialize_with = "key")]
+        path: PathBuf,
+        dry_run: bool,
+    },
+    RescaleProgress {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        percent: f64,
+        overall_percent: f64,
+    },
+    /// `generate-cache` is done, or with `--watch`, done with a batch of changes.
+    CacheSynced {
+        wallpapers: usize,
+        skipped: usize,
+        failed: usize,
+    },
+    CacheStats {
+        directories: Vec<CacheDirStats>,
+        /// How many rescaled wallpapers were evicted.
+        evicted: usize,
+        /// In bytes.
+        total: u64,
+        max: Option<u64>,
+    },
+    /// Removed by `cache prune` to make room, or would be with `--dry-run`.
+    Evicted {
+        #[serde(serialize_with = "key")]
+        path: PathBuf,
+        size: u64,
+        dry_run: bool,
+    },
+    CachePruned {
+        total: u64,
+        max: Option<u64>,
+    },
+    /// What is playing, after any command changing it.
+    Status(Status),
+    Event(Event),
+    ConfigReloaded,
+    History {
+        /// Most recent first.
+        entries: Vec<HistoryEntry>,
+    },
+    Schedule {
+        solar_events: Vec<SolarTime>,
+        entries: Vec<ScheduledWallpaper>,
+    },
+    Palette {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        theme: Theme,
+        #[serde(serialize_with = "key")]
+        exported_to: PathBuf,
+    },
+    Rated {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        rating: u8,
+    },
+    Favourite {
+        #[serde(serialize_with = "key")]
+        wallpaper: PathBuf,
+        favourite: bool,
+    },
+    ConfigWritten {
+        #[serde(serialize_with = "key")]
+        path: PathBuf,
+    },
+    ConfigMigrated {
+        /// `None` when the configuration was already up to date.
+        #[serde(serialize_with = "option_key")]
+        backup: Option<PathBuf>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+pub fn init(format: OutputFormat) {
+    // Only fails if called twice
+    let _ = FORMAT.set(format);
+}
+
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Writes a path like `path_to_key`, as JSON strings must be valid UTF-8.
+pub fn key<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path_to_key(path))
+}
+
+/// Reads a path written by `key`.
+pub fn from_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    String::deserialize(deserializer).map(|key| key_to_path(&key))
+}
+
+fn option_key<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_some(&path_to_key(path)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn print_json(message: &Message) {
+    let line = match serde_json::to_string(message) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Could not write the result as JSON: {}", e);
+            return;
+        }
+    };
+    let mut out = stdout().lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// Prints the result of a command: the message with `--output json`, or else
+/// what `human` prints.
+pub fn result(message: Message, human: impl FnOnce()) {
+    if is_json() {
+        print_json(&message);
+    } else {
+        human();
+    }
+}
+
+/// Prints the message with `--output json` only. People follow along through
+/// the log instead.
+pub fn event(message: Message) {
+    if is_json() {
+        print_json(&message);
+    }
+}
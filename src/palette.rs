@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::{stdout, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::ffmpeg::{decode_rgb_frame, get_duration};
+use crate::library::{Library, Wallpaper};
+use crate::output::{self, Message};
+use crate::runner::Runner;
+
+/// Frames are scaled down to this many pixels on each side before looking for
+/// colours, which is plenty and keeps it fast.
+const SAMPLE_SIZE: u32 = 64;
+/// How many distinct colours are picked out of the wallpaper. pywal's 16 colours
+/// are these, then a brighter version of each.
+const BASE_COLOURS: usize = 8;
+const EXPORT_DIR_NAME: &str = "wallpaper-manager-theme";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rgb([u8; 3]);
+
+const BLACK: Rgb = Rgb([0, 0, 0]);
+const WHITE: Rgb = Rgb([255, 255, 255]);
+
+impl Rgb {
+    fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2])
+    }
+
+    fn from_hex(hex: &str) -> Option<Rgb> {
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+    }
+
+    /// Relative luminance, from 0 to 255.
+    fn luminance(self) -> f64 {
+        0.2126 * self.0[0] as f64 + 0.7152 * self.0[1] as f64 + 0.0722 * self.0[2] as f64
+    }
+
+    /// Blends `amount` of `other` into this colour.
+    fn mix(self, other: Rgb, amount: f64) -> Rgb {
+        let channel = |i: usize| {
+            (self.0[i] as f64 * (1.0 - amount) + other.0[i] as f64 * amount).round() as u8
+        };
+        Rgb([channel(0), channel(1), channel(2)])
+    }
+}
+
+/// The colours of a wallpaper, in the layout of pywal's `colors.json`, which is
+/// also how they are cached.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Theme {
+    #[serde(
+        serialize_with = "crate::output::key",
+        deserialize_with = "crate::output::from_key"
+    )]
+    pub wallpaper: PathBuf,
+    pub alpha: String,
+    pub special: SpecialColours,
+    /// `color0` to `color15`
+    pub colors: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpecialColours {
+    pub background: String,
+    pub foreground: String,
+    pub cursor: String,
+}
+
+impl Theme {
+    /// The colours, from `color0` to `color15`.
+    fn ordered_colours(&self) -> Vec<(String, &str)> {
+        (0..BASE_COLOURS * 2)
+            .filter_map(|i| {
+                let name = format!("color{}", i);
+                let colour = self.colors.get(&name)?;
+                Some((name, colour.as_str()))
+            })
+            .collect()
+    }
+
+    fn xresources(&self) -> String {
+        let mut contents = format!(
+            "*.background: {}\n*.foreground: {}\n*.cursorColor: {}\n",
+            self.special.background, self.special.foreground, self.special.cursor
+        );
+        for (name, colour) in self.ordered_colours() {
+            contents += &format!("*.{}: {}\n", name, colour);
+        }
+        contents
+    }
+
+    fn shell_env(&self) -> String {
+        let mut contents = format!(
+            "wallpaper={}\nbackground='{}'\nforeground='{}'\ncursor='{}'\n",
+            shell_quote(&self.wallpaper.to_string_lossy()),
+            self.special.background,
+            self.special.foreground,
+            self.special.cursor
+        );
+        for (name, colour) in self.ordered_colours() {
+            contents += &format!("{}='{}'\n", name, colour);
+        }
+        contents
+    }
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Splits the pixels into `count` groups of similar colours, each time halving
+/// the group with the widest range of a channel at its median, and returns the
+/// average colour of each group.
+fn median_cut(pixels: Vec<Rgb>, count: usize) -> Vec<Rgb> {
+    let range = |group: &[Rgb], channel: usize| {
+        let values = group.iter().map(|pixel| pixel.0[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+    let widest_channel = |group: &[Rgb]| {
+        (0..3)
+            .max_by_key(|&channel| range(group, channel))
+            .unwrap_or(0)
+    };
+
+    let mut groups = vec![pixels];
+    while groups.len() < count {
+        let Some((index, _)) = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.len() > 1)
+            .max_by_key(|(_, group)| range(group, widest_channel(group)))
+        else {
+            break;
+        };
+        let mut group = groups.swap_remove(index);
+        let channel = widest_channel(&group);
+        group.sort_by_key(|pixel| pixel.0[channel]);
+        let upper_half = group.split_off(group.len() / 2);
+        groups.push(group);
+        groups.push(upper_half);
+    }
+
+    groups
+        .iter()
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let channel_average = |channel: usize| {
+                (group
+                    .iter()
+                    .map(|pixel| pixel.0[channel] as u64)
+                    .sum::<u64>()
+                    / group.len() as u64) as u8
+            };
+            Rgb([channel_average(0), channel_average(1), channel_average(2)])
+        })
+        .collect()
+}
+
+/// Builds a theme from raw RGB pixels. The darkest colour becomes the background
+/// and the lightest the foreground, both pushed further apart for contrast.
+fn theme_from_pixels(wallpaper: &Path, pixels: &[u8]) -> Theme {
+    let pixels = pixels
+        .chunks_exact(3)
+        .map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]]))
+        .collect();
+    let mut base = median_cut(pixels, BASE_COLOURS);
+    base.sort_by(|a, b| a.luminance().total_cmp(&b.luminance()));
+    // Very uniform wallpapers give fewer colours
+    while base.len() < BASE_COLOURS {
+        let last = base.last().copied().unwrap_or(BLACK);
+        base.push(last.mix(WHITE, 0.2));
+    }
+    base[0] = base[0].mix(BLACK, 0.5);
+    base[BASE_COLOURS - 1] = base[BASE_COLOURS - 1].mix(WHITE, 0.6);
+
+    let bright = base.iter().map(|colour| colour.mix(WHITE, 0.25));
+    let colors = base
+        .iter()
+        .copied()
+        .chain(bright)
+        .enumerate()
+        .map(|(i, colour)| (format!("color{}", i), colour.hex()))
+        .collect::<BTreeMap<_, _>>();
+
+    Theme {
+        wallpaper: wallpaper.to_path_buf(),
+        alpha: "100".to_string(),
+        special: SpecialColours {
+            background: base[0].hex(),
+            foreground: base[BASE_COLOURS - 1].hex(),
+            cursor: base[BASE_COLOURS - 1].hex(),
+        },
+        colors,
+    }
+}
+
+/// The palette of a wallpaper, from the cache if it's there. Otherwise it's
+/// extracted from the thumbnail, or from a frame in the middle of the wallpaper
+/// if there is no thumbnail yet, and cached.
+pub fn palette_for(
+    runner: &Runner,
+    config: &Config,
+    wallpaper: &Wallpaper,
+    regenerate: bool,
+) -> Result<Theme, String> {
+    if !regenerate {
+        if let Some(theme) = read_to_string(&wallpaper.palette)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+        {
+            return Ok(theme);
+        }
+    }
+
+    let pixels = if wallpaper.thumbnail.is_file() {
+        decode_rgb_frame(
+            runner,
+            &config.programs.ffmpeg,
+            &wallpaper.thumbnail,
+            None,
+            SAMPLE_SIZE,
+        )?
+    } else {
+        let duration = get_duration(runner, &config.programs.ffprobe, &wallpaper.source)?;
+        decode_rgb_frame(
+            runner,
+            &config.programs.ffmpeg,
+            &wallpaper.source,
+            duration.map(|duration| duration / 2.0),
+            SAMPLE_SIZE,
+        )?
+    };
+    let theme = theme_from_pixels(&wallpaper.source, &pixels);
+
+    let contents = serde_json::to_string_pretty(&theme).map_err(|e| e.to_string())?;
+    if let Some(parent) = wallpaper.palette.parent() {
+        runner
+            .create_dir_all(parent)
+            .map_err(|e| format!("Could not create palettes directory: {}", e))?;
+    }
+    if !runner.is_dry_run() {
+        write(&wallpaper.palette, contents).map_err(|e| {
+            format!(
+                "Could not cache palette at {}: {}",
+                wallpaper.palette.to_string_lossy(),
+                e
+            )
+        })?;
+    }
+    Ok(theme)
+}
+
+/// Writes the theme as pywal's `colors.json`, `colors.Xresources` and `colors.sh`.
+fn export(theme: &Theme, dir: &Path) -> Result<(), String> {
+    create_dir_all(dir).map_err(|e| {
+        format!(
+            "Could not create export directory {}: {}",
+            dir.to_string_lossy(),
+            e
+        )
+    })?;
+    let json = serde_json::to_string_pretty(theme).map_err(|e| e.to_string())?;
+    for (name, contents) in [
+        ("colors.json", json),
+        ("colors.Xresources", theme.xresources()),
+        ("colors.sh", theme.shell_env()),
+    ] {
+        let path = dir.join(name);
+        write(&path, contents)
+            .map_err(|e| format!("Could not write {}: {}", path.to_string_lossy(), e))?;
+    }
+    Ok(())
+}
+
+/// Prints the palette of a wallpaper, or of the one played last, and exports it.
+pub fn run(
+    config: &Config,
+    wallpaper: Option<&Path>,
+    export_dir: Option<&Path>,
+    regenerate: bool,
+) -> Result<(), String> {
+    let library = Library::scan(config)?;
+    let wallpaper = match wallpaper {
+        Some(path) => library
+            .find(path)
+            .ok_or_else(|| format!("No wallpaper found at {}", path.to_string_lossy()))?,
+        None => library.last_played(config).ok_or_else(|| {
+            "No wallpaper was played yet. Name the wallpaper to extract the palette of".to_string()
+        })?,
+    };
+
+    let theme = palette_for(&Runner::default(), config, wallpaper, regenerate)?;
+    let export_dir = export_dir
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| config.cache_dir.join(EXPORT_DIR_NAME));
+    export(&theme, &export_dir)?;
+
+    if output::is_json() {
+        output::event(Message::Palette {
+            wallpaper: wallpaper.id.as_path().to_path_buf(),
+            theme,
+            exported_to: export_dir,
+        });
+        return Ok(());
+    }
+    let colour_swatches = stdout().is_terminal();
+    println!("Palette of \"{}\"", wallpaper.id);
+    for (name, colour) in theme.ordered_colours() {
+        match Rgb::from_hex(colour).filter(|_| colour_swatches) {
+            Some(Rgb([r, g, b])) => {
+                println!(
+                    "{:<8} {} \x1b[48;2;{};{};{}m      \x1b[0m",
+                    name, colour, r, g, b
+                )
+            }
+            None => println!("{:<8} {}", name, colour),
+        }
+    }
+    println!("Theme exported to {}", export_dir.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::*;
+
+    #[test]
+    fn cached_themes_keep_paths_that_are_not_utf8() {
+        let wallpaper = Path::new(OsStr::from_bytes(b"/walls/bad\xff\\name.webm"));
+        let theme = theme_from_pixels(wallpaper, &[10, 20, 30, 200, 210, 220]);
+        let cached = serde_json::to_string(&theme).unwrap();
+        let read: Theme = serde_json::from_str(&cached).unwrap();
+        assert_eq!(read.wallpaper, wallpaper);
+    }
+}
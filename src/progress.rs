@@ -0,0 +1,145 @@
+use std::io::{stderr, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use log::{info, log_enabled, Level};
+
+use crate::ffmpeg::EncodeProgress;
+use crate::output::{self, Message};
+
+const BAR_WIDTH: usize = 30;
+/// How often a plain progress line is logged when stderr is not a terminal.
+const PLAIN_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Renders the progress of a batch of encodes on stderr. On a terminal, draws a bar
+/// for the current file and another for the whole batch. Otherwise, logs a plain
+/// line every few seconds, so logs stay readable.
+pub struct BatchProgress {
+    is_tty: bool,
+    total_duration: f64,
+    completed_duration: f64,
+    file_name: String,
+    file_duration: f64,
+    last_plain_report: Option<Instant>,
+    has_drawn: bool,
+}
+
+impl BatchProgress {
+    /// `total_duration` is the sum, in seconds, of the durations of every file in the batch.
+    pub fn new(total_duration: f64) -> Self {
+        BatchProgress {
+            // No bars with --quiet, nor in the middle of JSON events
+            is_tty: stderr().is_terminal() && log_enabled!(Level::Info) && !output::is_json(),
+            total_duration,
+            completed_duration: 0.0,
+            file_name: String::new(),
+            file_duration: 0.0,
+            last_plain_report: None,
+            has_drawn: false,
+        }
+    }
+
+    pub fn start_file(&mut self, file_name: &str, file_duration: Option<f64>) {
+        self.file_name = file_name.to_string();
+        self.file_duration = file_duration.unwrap_or(0.0);
+        self.last_plain_report = None;
+        self.has_drawn = false;
+    }
+
+    pub fn update(&mut self, progress: &EncodeProgress) {
+        let file_time = if progress.done {
+            self.file_duration
+        } else {
+            progress.out_time.min(self.file_duration)
+        };
+        let file_ratio = ratio(file_time, self.file_duration);
+        let overall_ratio = ratio(self.completed_duration + file_time, self.total_duration);
+        let file_eta = eta(self.file_duration - file_time, progress.speed);
+        let overall_eta = eta(
+            self.total_duration - self.completed_duration - file_time,
+            progress.speed,
+        );
+        let speed = progress
+            .speed
+            .map(|speed| format!("{:.2}x", speed))
+            .unwrap_or_else(|| "-".to_string());
+
+        if self.is_tty {
+            let mut out = stderr().lock();
+            if self.has_drawn {
+                // Go back to the start of the file line, so both lines get redrawn
+                let _ = write!(out, "\x1b[1A\r");
+            }
+            let _ = write!(
+                out,
+                "\x1b[2K{} {:>5.1}%  {:>6}  ETA {}  {}\n\x1b[2K{} {:>5.1}%          ETA {}  overall",
+                bar(file_ratio),
+                file_ratio * 100.0,
+                speed,
+                file_eta,
+                self.file_name,
+                bar(overall_ratio),
+                overall_ratio * 100.0,
+                overall_eta,
+            );
+            let _ = out.flush();
+            self.has_drawn = true;
+            return;
+        }
+
+        let should_report = progress.done
+            || self
+                .last_plain_report
+                .is_none_or(|last| last.elapsed() >= PLAIN_REPORT_INTERVAL);
+        if should_report {
+            info!(
+                "{}: {:.1}% (overall {:.1}%), speed {}, ETA {} (overall {})",
+                self.file_name,
+                file_ratio * 100.0,
+                overall_ratio * 100.0,
+                speed,
+                file_eta,
+                overall_eta,
+            );
+            output::event(Message::RescaleProgress {
+                wallpaper: self.file_name.clone().into(),
+                percent: file_ratio * 100.0,
+                overall_percent: overall_ratio * 100.0,
+            });
+            self.last_plain_report = Some(Instant::now());
+        }
+    }
+
+    pub fn finish_file(&mut self) {
+        self.completed_duration += self.file_duration;
+        if self.is_tty && self.has_drawn {
+            eprintln!();
+        }
+    }
+}
+
+fn ratio(done: f64, total: f64) -> f64 {
+    if total <= 0.0 {
+        return 0.0;
+    }
+    (done / total).clamp(0.0, 1.0)
+}
+
+fn bar(ratio: f64) -> String {
+    let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn eta(remaining_media_time: f64, speed: Option<f64>) -> String {
+    match speed {
+        Some(speed) if speed > 0.0 => {
+            let seconds = (remaining_media_time.max(0.0) / speed).round() as u64;
+            format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )
+        }
+        _ => "--:--:--".to_string(),
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::Path;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::control::Client;
+use crate::history::History;
+use crate::library::{Library, Wallpaper, WallpaperId};
+use crate::output::{self, Message};
+use crate::utils::{key_to_path, path_to_key};
+
+/// Ratings go from 0 to this. A wallpaper rated 0 is never picked at random.
+pub const MAX_RATING: u8 = 5;
+/// How likely unrated wallpapers are to be picked, compared with rated ones.
+const UNRATED_WEIGHT: u32 = 3;
+/// Favourites are this many times more likely to be picked.
+const FAVOURITE_FACTOR: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Rating {
+    pub rating: Option<u8>,
+    #[serde(default)]
+    pub favourite: bool,
+}
+
+impl Rating {
+    fn weight(&self) -> u32 {
+        let weight = self.rating.map(u32::from).unwrap_or(UNRATED_WEIGHT);
+        if self.favourite {
+            weight * FAVOURITE_FACTOR
+        } else {
+            weight
+        }
+    }
+}
+
+/// The ratings and favourites set by hand, by wallpaper path relative to the
+/// wallpapers directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Ratings {
+    #[serde(default)]
+    wallpapers: BTreeMap<String, Rating>,
+}
+
+impl Ratings {
+    /// Reads the ratings. A missing file means nothing was rated yet.
+    pub fn read(path: &Path) -> Result<Ratings, String> {
+        match read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("Invalid ratings file {}: {}", path.to_string_lossy(), e)),
+            Err(_) if !path.exists() => Ok(Ratings::default()),
+            Err(e) => Err(format!(
+                "Could not read ratings at {}: {}",
+                path.to_string_lossy(),
+                e
+            )),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {}", parent.to_string_lossy(), e))?;
+        }
+        let contents =
+            toml::to_string(self).map_err(|e| format!("Failed to serialize ratings: {}", e))?;
+        write(path, contents).map_err(|e| {
+            format!(
+                "Could not write ratings at {}: {}",
+                path.to_string_lossy(),
+                e
+            )
+        })
+    }
+
+    pub fn get(&self, id: &WallpaperId) -> Rating {
+        self.wallpapers
+            .get(&path_to_key(id.as_path()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Changes the rating of a wallpaper, forgetting it once there is nothing left
+    /// to remember.
+    pub fn update(&mut self, id: &WallpaperId, change: impl FnOnce(&mut Rating)) {
+        let key = path_to_key(id.as_path());
+        let rating = self.wallpapers.entry(key.clone()).or_default();
+        change(rating);
+        if rating.rating.is_none() && !rating.favourite {
+            self.wallpapers.remove(&key);
+        }
+    }
+}
+
+/// Picks one of the candidates at random, weighted by their ratings. The
+/// `avoid_recent` wallpapers shown last and `current` are left out, unless
+/// nothing else is left.
+pub fn pick_weighted<'a>(
+    config: &Config,
+    candidates: &[&'a Wallpaper],
+    current: Option<&WallpaperId>,
+    avoid_recent: usize,
+) -> Option<&'a Wallpaper> {
+    // A broken ratings file shouldn't stop the wallpaper from changing
+    let ratings = Ratings::read(&config.ratings_path).unwrap_or_else(|e| {
+        warn!("{}", e);
+        Ratings::default()
+    });
+    let history = History::read(&config.cache_dir);
+    let recent: HashSet<WallpaperId> = history
+        .entries()
+        .iter()
+        .rev()
+        .take(avoid_recent)
+        .map(|entry| entry.id())
+        .collect();
+
+    let weighted: Vec<(&Wallpaper, u32)> = candidates
+        .iter()
+        .map(|wallpaper| (*wallpaper, ratings.get(&wallpaper.id).weight()))
+        .filter(|(_, weight)| *weight > 0)
+        .collect();
+    let not_current = |(wallpaper, _): &&(&Wallpaper, u32)| Some(&wallpaper.id) != current;
+    let not_recent = |(wallpaper, _): &&(&Wallpaper, u32)| !recent.contains(&wallpaper.id);
+
+    let fresh: Vec<_> = weighted
+        .iter()
+        .filter(not_current)
+        .filter(not_recent)
+        .copied()
+        .collect();
+    if !fresh.is_empty() {
+        return pick(&fresh);
+    }
+    let not_current: Vec<_> = weighted.iter().filter(not_current).copied().collect();
+    if !not_current.is_empty() {
+        return pick(&not_current);
+    }
+    pick(&weighted)
+}
+
+fn pick<'a>(weighted: &[(&'a Wallpaper, u32)]) -> Option<&'a Wallpaper> {
+    let total: u32 = weighted.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut remaining = fastrand::u32(..total);
+    for (wallpaper, weight) in weighted {
+        if remaining < *weight {
+            return Some(wallpaper);
+        }
+        remaining -= weight;
+    }
+    None
+}
+
+/// The wallpaper named on the command line, or else the one playing.
+fn named_or_playing<'a>(
+    config: &Config,
+    library: &'a Library,
+    wallpaper: Option<&Path>,
+) -> Result<&'a Wallpaper, String> {
+    if let Some(path) = wallpaper {
+        return library
+            .find(path)
+            .ok_or_else(|| format!("No wallpaper found at {}", path.to_string_lossy()));
+    }
+    let playing = Client::connect(config)
+        .and_then(|mut client| client.status().ok())
+        .and_then(|status| status.wallpaper)
+        .map(|key| key_to_path(&key));
+    match playing {
+        Some(path) => library.find(&path),
+        None => library.last_played(config),
+    }
+    .ok_or_else(|| "No wallpaper was played yet. Name the wallpaper".to_string())
+}
+
+pub fn rate(config: &Config, wallpaper: Option<&Path>, rating: u8) -> Result<(), String> {
+    let library = Library::scan(config)?;
+    let wallpaper = named_or_playing(config, &library, wallpaper)?;
+    let mut ratings = Ratings::read(&config.ratings_path)?;
+    ratings.update(&wallpaper.id, |current| current.rating = Some(rating));
+    ratings.write(&config.ratings_path)?;
+    output::result(
+        Message::Rated {
+            wallpaper: wallpaper.id.as_path().to_path_buf(),
+            rating,
+        },
+        || println!("Rated \"{}\" {}/{}", wallpaper.id, rating, MAX_RATING),
+    );
+    Ok(())
+}
+
+pub fn set_favourite(
+    config: &Config,
+    wallpaper: Option<&Path>,
+    favourite: bool,
+) -> Result<(), String> {
+    let library = Library::scan(config)?;
+    let wallpaper = named_or_playing(config, &library, wallpaper)?;
+    let mut ratings = Ratings::read(&config.ratings_path)?;
+    ratings.update(&wallpaper.id, |current| current.favourite = favourite);
+    ratings.write(&config.ratings_path)?;
+    output::result(
+        Message::Favourite {
+            wallpaper: wallpaper.id.as_path().to_path_buf(),
+            favourite,
+        },
+        || {
+            if favourite {
+                println!("\"{}\" is now a favourite", wallpaper.id);
+            } else {
+                println!("\"{}\" is no longer a favourite", wallpaper.id);
+            }
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A cache directory and ratings file of their own, removed when dropped.
+    struct TestConfig {
+        config: Config,
+        dir: PathBuf,
+    }
+
+    impl TestConfig {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "wallpaper-manager-ratings-{}-{}",
+                name,
+                std::process::id()
+            ));
+            create_dir_all(&dir).unwrap();
+            let config = Config {
+                cache_dir: dir.clone(),
+                ratings_path: dir.join("ratings.toml"),
+                ..Config::default()
+            };
+            TestConfig { config, dir }
+        }
+    }
+
+    impl Drop for TestConfig {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.dir);
+        }
+    }
+
+    fn wallpaper(id: &str) -> Wallpaper {
+        Wallpaper {
+            id: WallpaperId::new(id),
+            source: PathBuf::from("/walls").join(id),
+            thumbnail: PathBuf::new(),
+            rescaled: PathBuf::new(),
+            palette: PathBuf::new(),
+        }
+    }
+
+    /// Every wallpaper picked out of many tries.
+    fn picked_ids(
+        config: &Config,
+        candidates: &[&Wallpaper],
+        current: Option<&WallpaperId>,
+        avoid_recent: usize,
+    ) -> HashSet<String> {
+        (0..200)
+            .filter_map(|_| pick_weighted(config, candidates, current, avoid_recent))
+            .map(|wallpaper| wallpaper.id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn wallpapers_rated_0_are_never_picked() {
+        let test = TestConfig::new("rated-0");
+        let (liked, banned) = (wallpaper("liked.mp4"), wallpaper("banned.mp4"));
+        let mut ratings = Ratings::default();
+        ratings.update(&banned.id, |rating| rating.rating = Some(0));
+        ratings.write(&test.config.ratings_path).unwrap();
+
+        let picked = picked_ids(&test.config, &[&liked, &banned], None, 0);
+        assert_eq!(picked, HashSet::from(["liked.mp4".to_string()]));
+        assert!(pick_weighted(&test.config, &[&banned], None, 0).is_none());
+    }
+
+    #[test]
+    fn the_last_wallpapers_shown_are_avoided() {
+        let test = TestConfig::new("recent");
+        let wallpapers: Vec<_> = ["a.mp4", "b.mp4", "c.mp4", "d.mp4"]
+            .into_iter()
+            .map(wallpaper)
+            .collect();
+        let candidates: Vec<_> = wallpapers.iter().collect();
+        let mut history = History::read(&test.config.cache_dir);
+        for wallpaper in &wallpapers[..3] {
+            history.record(wallpaper, 10);
+        }
+        history.write(&test.config.cache_dir).unwrap();
+
+        // c.mp4 is current, and a.mp4 and b.mp4 were shown just before
+        let current = Some(&wallpapers[2].id);
+        let picked = picked_ids(&test.config, &candidates, current, 3);
+        assert_eq!(picked, HashSet::from(["d.mp4".to_string()]));
+
+        // Only c.mp4 and b.mp4 are recent enough to be avoided
+        let picked = picked_ids(&test.config, &candidates, current, 2);
+        assert_eq!(
+            picked,
+            HashSet::from(["a.mp4".to_string(), "d.mp4".to_string()])
+        );
+    }
+
+    #[test]
+    fn recent_wallpapers_are_picked_when_nothing_else_is_left() {
+        let test = TestConfig::new("fallback");
+        let (a, b) = (wallpaper("a.mp4"), wallpaper("b.mp4"));
+        let mut history = History::read(&test.config.cache_dir);
+        history.record(&a, 10);
+        history.record(&b, 10);
+        history.write(&test.config.cache_dir).unwrap();
+
+        let picked = picked_ids(&test.config, &[&a, &b], Some(&b.id), 5);
+        assert_eq!(picked, HashSet::from(["a.mp4".to_string()]));
+    }
+}
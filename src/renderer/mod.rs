@@ -0,0 +1,104 @@
+mod wayland;
+mod x11;
+
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use log::warn;
+
+use crate::cache::CacheUsage;
+use crate::config::{Config, RendererKind};
+use crate::hooks::{self, RunningHooks};
+use crate::library::Wallpaper;
+use crate::runner::Runner;
+
+use wayland::Wayland;
+use x11::X11;
+
+/// Draws wallpapers on the desktop.
+pub trait Renderer {
+    /// The long-running process started by the daemon, which draws wallpapers.
+    fn daemon_command(&self, config: &Config) -> Command;
+
+    /// The process playing videos, when it isn't the daemon's, without the video
+    /// to start with. The daemon starts it with the first video.
+    fn player_command(&self, _config: &Config) -> Option<Command> {
+        None
+    }
+
+    /// Waits until the daemon's process accepts wallpapers, up to `timeout`.
+    fn wait_until_ready(
+        &self,
+        runner: &Runner,
+        config: &Config,
+        timeout: Duration,
+    ) -> io::Result<()>;
+
+    /// Replaces the current wallpaper with the file at `path`, which is the
+    /// wallpaper's source or rescaled copy.
+    fn show(
+        &self,
+        runner: &Runner,
+        config: &Config,
+        wallpaper: &Wallpaper,
+        path: &Path,
+    ) -> io::Result<()>;
+}
+
+pub fn for_config(config: &Config) -> Box<dyn Renderer> {
+    match config.renderer {
+        RendererKind::X11 => Box::new(X11),
+        RendererKind::Wayland => Box::new(Wayland),
+    }
+}
+
+/// Starts the renderer's daemon in the background, in its own process group, so
+/// it can be stopped along with every process it starts.
+pub fn spawn(runner: &Runner, config: &Config) -> io::Result<Option<Child>> {
+    runner.spawn(for_config(config).daemon_command(config).process_group(0))
+}
+
+/// Whether videos are played by a process of their own, started by `spawn_player`.
+pub fn has_player(config: &Config) -> bool {
+    for_config(config).player_command(config).is_some()
+}
+
+/// Starts the renderer's video player on `path` in the background, in its own
+/// process group.
+pub fn spawn_player(runner: &Runner, config: &Config, path: &Path) -> io::Result<Option<Child>> {
+    match for_config(config).player_command(config) {
+        Some(mut command) => runner.spawn(command.arg(path).process_group(0)),
+        None => Ok(None),
+    }
+}
+
+pub fn wait_until_ready(runner: &Runner, config: &Config, timeout: Duration) -> io::Result<()> {
+    for_config(config).wait_until_ready(runner, config, timeout)
+}
+
+/// Plays a wallpaper again after the daemon was restarted, without recording it
+/// or running the hooks, since it hasn't changed.
+pub fn reload(config: &Config, wallpaper: &Wallpaper) -> io::Result<()> {
+    for_config(config).show(
+        &Runner::default(),
+        config,
+        wallpaper,
+        wallpaper.playable_path(),
+    )
+}
+
+/// Plays a wallpaper, then starts the `on_change` hooks.
+pub fn show(config: &Config, wallpaper: &Wallpaper) -> io::Result<RunningHooks> {
+    let path = wallpaper.playable_path();
+    for_config(config).show(&Runner::default(), config, wallpaper, path)?;
+
+    let mut usage = CacheUsage::read(&config.cache_dir);
+    usage.record(path);
+    if let Err(e) = usage.write(&config.cache_dir) {
+        warn!("{}", e);
+    }
+    Ok(hooks::run_on_change(config, wallpaper))
+}
@@ -0,0 +1,231 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use super::Renderer;
+use crate::config::Config;
+use crate::library::Wallpaper;
+use crate::mpv;
+use crate::runner::Runner;
+use crate::supervisor;
+use crate::utils::make_error_message_after_command_call;
+
+/// mpvpaper for videos and swww for still images. The daemon runs swww-daemon,
+/// and mpvpaper from the first video on. mpvpaper is stopped when a still image
+/// is shown, so it doesn't cover it, until the next video.
+pub struct Wayland;
+
+/// Which outputs mpvpaper draws on.
+const ALL_OUTPUTS: &str = "*";
+/// How long to wait for mpvpaper's socket when it starts.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Held while mpvpaper starts, so wallpapers shown at the same time don't start
+/// one each.
+static STARTING: Mutex<()> = Mutex::new(());
+
+/// One mpv option in mpvpaper's `-o` format, which splits options at whitespace
+/// outside double quotes. A value with whitespace is quoted, or written in mpv's
+/// `%length%value` form if it contains double quotes as well.
+fn mpv_option(option: &OsStr) -> OsString {
+    let bytes = option.as_bytes();
+    if !bytes.iter().any(u8::is_ascii_whitespace) {
+        return option.to_owned();
+    }
+    let (name, value) = match bytes.iter().position(|&byte| byte == b'=') {
+        Some(equals) => (&bytes[..=equals], &bytes[equals + 1..]),
+        None => (&b""[..], bytes),
+    };
+    let mut quoted = name.to_vec();
+    if value.contains(&b'"') {
+        quoted.extend(format!("%{}%", value.len()).bytes());
+        quoted.extend(value);
+    } else {
+        quoted.push(b'"');
+        quoted.extend(value);
+        quoted.push(b'"');
+    }
+    OsString::from_vec(quoted)
+}
+
+impl Wayland {
+    /// mpv's options, in mpvpaper's `-o` format.
+    fn mpv_options(config: &Config) -> OsString {
+        let mut ipc_server_arg = OsString::from("--input-ipc-server=");
+        ipc_server_arg.push(&config.socket_path);
+        let options = mpv::DEFAULT_ARGS
+            .iter()
+            .map(OsStr::new)
+            .chain([ipc_server_arg.as_os_str()])
+            .chain(config.programs.mpv.args.iter().map(OsStr::new))
+            .map(mpv_option);
+        let mut joined = OsString::new();
+        for option in options {
+            if !joined.is_empty() {
+                joined.push(" ");
+            }
+            joined.push(option);
+        }
+        joined
+    }
+
+    /// mpvpaper, without the video to start with.
+    fn mpvpaper_command(config: &Config) -> Command {
+        let mpvpaper = &config.programs.mpvpaper;
+        let mut command = mpvpaper.command();
+        command
+            .arg("-o")
+            .arg(Self::mpv_options(config))
+            .args(&mpvpaper.args)
+            .arg(ALL_OUTPUTS);
+        command
+    }
+
+    fn play_video(runner: &Runner, config: &Config, path: &Path) -> io::Result<()> {
+        let _starting = STARTING.lock().unwrap_or_else(|e| e.into_inner());
+        supervisor::remember_video(config, path);
+        if mpv::is_running(&config.socket_path) {
+            return mpv::load_file(&config.socket_path, path);
+        }
+        if supervisor::wake(config) {
+            mpv::wait_for_socket(&config.socket_path, START_TIMEOUT)?;
+            // It may have started with a video shown just before
+            return mpv::load_file(&config.socket_path, path);
+        }
+
+        // Without a daemon to start it again, it keeps running after this process
+        // exits, unsupervised, so it's detached from this process' terminal and
+        // signals
+        let Some(mut child) = runner.spawn(
+            Self::mpvpaper_command(config)
+                .arg(path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0),
+        )?
+        else {
+            return Ok(());
+        };
+        if let Err(e) = mpv::wait_for_socket(&config.socket_path, START_TIMEOUT) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        // Reaped whenever it exits, so it doesn't linger as a zombie
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    fn show_still(runner: &Runner, config: &Config, path: &Path) -> io::Result<()> {
+        let swww = &config.programs.swww;
+        let output = runner.output(
+            swww.command()
+                .arg("img")
+                .args(&swww.args)
+                .arg(path)
+                .stderr(Stdio::inherit()),
+        )?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                swww.name(),
+                output.status
+            )));
+        }
+
+        if mpv::is_running(&config.socket_path) {
+            supervisor::stopping_on_purpose(config)?;
+            if let Err(e) = mpv::quit(&config.socket_path) {
+                supervisor::not_stopping(config);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Wayland {
+    fn daemon_command(&self, config: &Config) -> Command {
+        config.programs.swww_daemon.command()
+    }
+
+    fn player_command(&self, config: &Config) -> Option<Command> {
+        Some(Self::mpvpaper_command(config))
+    }
+
+    fn wait_until_ready(
+        &self,
+        runner: &Runner,
+        config: &Config,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let swww = &config.programs.swww;
+        let started = Instant::now();
+        loop {
+            let output = runner
+                .output(swww.command().arg("query").stderr(Stdio::null()))
+                .map_err(|e| {
+                    io::Error::other(make_error_message_after_command_call(&swww.name(), e))
+                })?;
+            if output.status.success() {
+                return Ok(());
+            }
+            if started.elapsed() >= timeout {
+                return Err(io::Error::other(format!(
+                    "{} did not start within {} seconds",
+                    config.programs.swww_daemon.name(),
+                    timeout.as_secs()
+                )));
+            }
+            sleep(Duration::from_millis(200));
+        }
+    }
+
+    fn show(
+        &self,
+        runner: &Runner,
+        config: &Config,
+        wallpaper: &Wallpaper,
+        path: &Path,
+    ) -> io::Result<()> {
+        if wallpaper.is_still_image() {
+            Self::show_still(runner, config, path)
+        } else {
+            Self::play_video(runner, config, path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mpv_option_leaves_options_without_whitespace_alone() {
+        assert_eq!(mpv_option(OsStr::new("--no-audio")), "--no-audio");
+        assert_eq!(mpv_option(OsStr::new("--panscan=1.0")), "--panscan=1.0");
+    }
+
+    #[test]
+    fn mpv_option_quotes_values_with_whitespace() {
+        assert_eq!(
+            mpv_option(OsStr::new("--input-ipc-server=/tmp/my sockets/mpv")),
+            "--input-ipc-server=\"/tmp/my sockets/mpv\""
+        );
+    }
+
+    #[test]
+    fn mpv_option_counts_values_with_double_quotes() {
+        assert_eq!(
+            mpv_option(OsStr::new("--title=say \"hi\"")),
+            "--title=%8%say \"hi\""
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use super::Renderer;
+use crate::config::Config;
+use crate::library::Wallpaper;
+use crate::mpv;
+use crate::runner::Runner;
+use crate::supervisor;
+
+/// mpv playing inside an xwinwrap window, below every other window. Wallpapers
+/// are changed through mpv's IPC socket. Still images are set on the root window
+/// by the static setter instead, and the daemon is stopped until the next video,
+/// so it doesn't take memory or cover them.
+pub struct X11;
+
+/// How long to wait for the daemon when a video restarts it.
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl X11 {
+    fn play_video(&self, runner: &Runner, config: &Config, path: &Path) -> io::Result<()> {
+        if !mpv::is_running(&config.socket_path) {
+            // Without a daemon to start it again, it keeps running after this
+            // process exits, unsupervised
+            if !supervisor::wake(config) {
+                runner.spawn(
+                    self.daemon_command(config)
+                        .stdin(Stdio::null())
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .process_group(0),
+                )?;
+            }
+            self.wait_until_ready(runner, config, RESTART_TIMEOUT)?;
+        }
+        mpv::load_file(&config.socket_path, path)
+    }
+
+    fn show_still(runner: &Runner, config: &Config, path: &Path) -> io::Result<()> {
+        let setter = &config.programs.static_setter;
+        let output = runner.output(
+            setter
+                .command()
+                .args(&setter.args)
+                .arg(path)
+                .stderr(Stdio::inherit()),
+        )?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                setter.name(),
+                output.status
+            )));
+        }
+
+        // xwinwrap exits along with mpv
+        if mpv::is_running(&config.socket_path) {
+            supervisor::stopping_on_purpose(config)?;
+            if let Err(e) = mpv::quit(&config.socket_path) {
+                supervisor::not_stopping(config);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for X11 {
+    fn daemon_command(&self, config: &Config) -> Command {
+        let programs = &config.programs;
+        let mut ipc_server_arg = OsString::from("--input-ipc-server=");
+        ipc_server_arg.push(&config.socket_path);
+
+        let mut command = programs.xwinwrap.command();
+        command
+            .args(&programs.xwinwrap.args)
+            .arg("--")
+            .arg(&programs.mpv.path)
+            .args(["-wid", "WID", "--player-operation-mode=cplayer"])
+            .args(mpv::DEFAULT_ARGS)
+            .arg(ipc_server_arg)
+            .args(&programs.mpv.args);
+        command
+    }
+
+    fn wait_until_ready(
+        &self,
+        _runner: &Runner,
+        config: &Config,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        mpv::wait_for_socket(&config.socket_path, timeout)
+    }
+
+    fn show(
+        &self,
+        runner: &Runner,
+        config: &Config,
+        wallpaper: &Wallpaper,
+        path: &Path,
+    ) -> io::Result<()> {
+        if wallpaper.is_still_image() {
+            Self::show_still(runner, config, path)
+        } else {
+            self.play_video(runner, config, path)
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::ffi::OsStr;
+use std::fs::{create_dir_all, remove_file};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Child, Command, Output};
+
+/// Every external program this tool starts is launched through a `Runner`, so a
+/// single place decides whether commands are echoed, and whether commands that
+/// change something on disk are executed or only reported.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Runner {
+    dry_run: bool,
+    print_commands: bool,
+}
+
+impl Runner {
+    pub fn new(dry_run: bool, print_commands: bool) -> Self {
+        Runner {
+            dry_run,
+            print_commands,
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Runs a command that only reads (like ffprobe) and collects its output. These
+    /// run even on dry runs, since their results decide what would be done.
+    pub fn output(&self, command: &mut Command) -> io::Result<Output> {
+        self.echo(command, false);
+        command.output()
+    }
+
+    /// Spawns a command that has side effects. On dry runs, the command is only
+    /// reported, and `None` is returned.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Option<Child>> {
+        self.echo(command, self.dry_run);
+        if self.dry_run {
+            return Ok(None);
+        }
+        command.spawn().map(Some)
+    }
+
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        create_dir_all(path)
+    }
+
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        remove_file(path)
+    }
+
+    fn echo(&self, command: &Command, skipped: bool) {
+        if !self.print_commands {
+            return;
+        }
+        let prefix = if skipped { "Would run" } else { "Running" };
+        // Asked for explicitly, so shown whatever the log level
+        eprintln!("{}: {}", prefix, format_command(command));
+    }
+}
+
+/// Formats the command as a line that could be pasted into a shell.
+pub fn format_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &OsStr) -> String {
+    let Some(arg) = arg.to_str() else {
+        // Use bash's $'...' quoting, which can represent any byte
+        let escaped: String = arg
+            .as_bytes()
+            .iter()
+            .map(|&byte| match byte {
+                b' '..=b'~' if byte != b'\'' && byte != b'\\' => (byte as char).to_string(),
+                _ => format!("\\x{:02x}", byte),
+            })
+            .collect();
+        return format!("$'{}'", escaped);
+    };
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if is_safe {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_leaves_safe_arguments_alone() {
+        assert_eq!(shell_quote(OsStr::new("-c:v")), "-c:v");
+        assert_eq!(shell_quote(OsStr::new("/tmp/a_b-1.mp4")), "/tmp/a_b-1.mp4");
+    }
+
+    #[test]
+    fn shell_quote_quotes_special_characters() {
+        assert_eq!(shell_quote(OsStr::new("")), "''");
+        assert_eq!(shell_quote(OsStr::new("a b")), "'a b'");
+        assert_eq!(shell_quote(OsStr::new("it's")), r"'it'\''s'");
+        assert_eq!(shell_quote(OsStr::new("say \"hi\"")), "'say \"hi\"'");
+        assert_eq!(shell_quote(OsStr::new("two\nlines")), "'two\nlines'");
+        assert_eq!(shell_quote(OsStr::new("forêt")), "'forêt'");
+    }
+
+    #[test]
+    fn shell_quote_writes_invalid_utf8_as_hex() {
+        assert_eq!(
+            shell_quote(OsStr::from_bytes(b"a\x80'\\b")),
+            r"$'a\x80\x27\x5cb'"
+        );
+    }
+
+    #[test]
+    fn format_command_quotes_each_argument() {
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-i", "my wallpaper.mp4"])
+            .arg(OsStr::from_bytes(b"out\xff.mp4"));
+        assert_eq!(
+            format_command(&command),
+            r"ffmpeg -i 'my wallpaper.mp4' $'out\xff.mp4'"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeDelta};
+use log::{error, info, warn};
+
+use crate::config::{Config, ScheduleStart, ScheduleTarget};
+use crate::control::Controller;
+use crate::library::{Library, Wallpaper, WallpaperId};
+use crate::output::{self, Message, ScheduledWallpaper, SolarTime};
+use crate::ratings;
+use crate::renderer;
+use crate::runner::Runner;
+use crate::solar::{self, SolarEvent};
+
+/// How long to wait for the renderer when the daemon starts.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest the scheduler sleeps at once, so it catches up quickly after a
+/// suspend or a clock change.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// When an entry starts on `date`, in local time. `None` if it doesn't start
+/// that day, like an entry following sunset during a polar day.
+fn start_on(config: &Config, start: &ScheduleStart, date: NaiveDate) -> Option<NaiveDateTime> {
+    match start {
+        ScheduleStart::Time(time) => Some(date.and_time(*time)),
+        ScheduleStart::Solar {
+            event,
+            offset_minutes,
+        } => {
+            let location = config.location.as_ref()?;
+            let time = solar::event_time(*event, date, location.latitude, location.longitude)?;
+            Some(time.with_timezone(&Local).naive_local() + TimeDelta::minutes(*offset_minutes))
+        }
+    }
+}
+
+/// When each schedule entry starts on `date`, along with the entry's index, in
+/// chronological order.
+fn transitions(config: &Config, date: NaiveDate) -> Vec<(NaiveDateTime, usize)> {
+    let mut transitions: Vec<_> = config
+        .schedule
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| Some((start_on(config, &entry.start, date)?, index)))
+        .collect();
+    transitions.sort();
+    transitions
+}
+
+/// The transition in effect at `now`, and when the next one happens. Entries
+/// started yesterday are considered, as the last one lasts past midnight.
+fn current_transition(
+    config: &Config,
+    now: NaiveDateTime,
+) -> Option<((NaiveDateTime, usize), NaiveDateTime)> {
+    let today = now.date();
+    let mut all: Vec<_> = [today.pred_opt()?, today, today.succ_opt()?]
+        .into_iter()
+        .flat_map(|date| transitions(config, date))
+        .collect();
+    all.sort();
+
+    let current = all.iter().rev().find(|(start, _)| *start <= now)?;
+    let (next_start, _) = all.iter().find(|(start, _)| *start > now)?;
+    Some((*current, *next_start))
+}
+
+/// Picks a wallpaper for a schedule entry, at random and weighted by ratings
+/// when it covers several.
+fn pick<'a>(
+    config: &Config,
+    library: &'a Library,
+    target: &ScheduleTarget,
+) -> Option<&'a Wallpaper> {
+    let candidates = match target {
+        ScheduleTarget::Wallpaper(path) => return library.get(&WallpaperId::new(path)),
+        ScheduleTarget::Tag(tag) => library.with_tag(config, tag),
+        ScheduleTarget::Folder(folder) => library.in_folder(folder),
+    };
+    ratings::pick_weighted(config, &candidates, None, config.random_avoid_recent)
+}
+
+fn describe_start(start: &ScheduleStart) -> String {
+    match start {
+        ScheduleStart::Time(time) => time.format("%H:%M").to_string(),
+        ScheduleStart::Solar {
+            event,
+            offset_minutes: 0,
+        } => event.name().to_string(),
+        ScheduleStart::Solar {
+            event,
+            offset_minutes,
+        } => format!(
+            "{}{}{:02}:{:02}",
+            event.name(),
+            if *offset_minutes < 0 { '-' } else { '+' },
+            offset_minutes.abs() / 60,
+            offset_minutes.abs() % 60
+        ),
+    }
+}
+
+fn describe(target: &ScheduleTarget) -> String {
+    match target {
+        ScheduleTarget::Wallpaper(path) => format!("wallpaper {}", path.to_string_lossy()),
+        ScheduleTarget::Tag(tag) => format!("tag {}", tag),
+        ScheduleTarget::Folder(folder) => format!("folder {}", folder.to_string_lossy()),
+    }
+}
+
+/// Prints when the sun rises and sets today, and when each schedule entry starts.
+pub fn show(config: &Config) {
+    let now = Local::now().naive_local();
+    let today = now.date();
+
+    let solar_events: Vec<SolarTime> = match &config.location {
+        Some(location) => SolarEvent::ALL
+            .into_iter()
+            .map(|event| SolarTime {
+                event: event.name(),
+                time: solar::event_time(event, today, location.latitude, location.longitude)
+                    .map(|time| time.with_timezone(&Local).format("%H:%M").to_string()),
+            })
+            .collect(),
+        None => vec![],
+    };
+    let current = current_transition(config, now).map(|(current, _)| current);
+    let today_transitions = transitions(config, today);
+    let entries: Vec<ScheduledWallpaper> = today_transitions
+        .iter()
+        .map(|&(start, index)| {
+            let entry = &config.schedule[index];
+            let (wallpaper, tag, folder) = match &entry.target {
+                ScheduleTarget::Wallpaper(path) => (Some(path.clone()), None, None),
+                ScheduleTarget::Tag(tag) => (None, Some(tag.clone()), None),
+                ScheduleTarget::Folder(folder) => (None, None, Some(folder.clone())),
+            };
+            ScheduledWallpaper {
+                start: start.format("%H:%M").to_string(),
+                trigger: describe_start(&entry.start),
+                wallpaper,
+                tag,
+                folder,
+                current: current == Some((start, index)),
+            }
+        })
+        .collect();
+
+    if output::is_json() {
+        output::event(Message::Schedule {
+            solar_events,
+            entries,
+        });
+        return;
+    }
+
+    for solar_time in &solar_events {
+        match &solar_time.time {
+            Some(time) => println!("{:<8} {}", solar_time.event, time),
+            None => println!("{:<8} does not happen today", solar_time.event),
+        }
+    }
+    if !solar_events.is_empty() {
+        println!();
+    }
+    if config.schedule.is_empty() {
+        println!("No schedule configured");
+        return;
+    }
+    for (entry, (_, index)) in entries.iter().zip(today_transitions) {
+        println!(
+            "{} {:<14} {}{}",
+            entry.start,
+            entry.trigger,
+            describe(&config.schedule[index].target),
+            if entry.current { " (now)" } else { "" }
+        );
+    }
+}
+
+/// Plays the wallpaper scheduled for the current time as soon as the renderer is
+/// up, then switches at every boundary of the schedule. The schedule is read
+/// from the controller each time, so it follows configuration reloads. Never
+/// returns.
+pub fn run(controller: &Controller) {
+    if let Err(e) =
+        renderer::wait_until_ready(&Runner::default(), &controller.config(), STARTUP_TIMEOUT)
+    {
+        warn!("The renderer did not become ready: {}", e);
+    }
+
+    let mut current = None;
+    loop {
+        let config = controller.config();
+        let now = Local::now().naive_local();
+        let Some((transition, next_start)) = current_transition(&config, now) else {
+            // Nothing starts around now, like when following the sun in a polar
+            // night, or when there is no schedule
+            sleep(MAX_SLEEP);
+            continue;
+        };
+
+        if current != Some(transition) {
+            current = Some(transition);
+            let target = &config.schedule[transition.1].target;
+            match Library::scan(&config) {
+                Err(e) => error!("{}", e),
+                Ok(library) => match pick(&config, &library, target) {
+                    None => warn!("Schedule: no wallpaper found for {}", describe(target)),
+                    Some(wallpaper) => {
+                        info!("Schedule: switching to \"{}\"", wallpaper.id);
+                        if let Err(e) = controller.show(wallpaper) {
+                            error!("Failed to show the wallpaper: {}", e);
+                        }
+                    }
+                },
+            }
+        }
+
+        let until_next = (next_start - now).to_std().unwrap_or_default();
+        sleep(until_next.min(MAX_SLEEP));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+    use crate::config::ScheduleEntry;
+
+    fn at(date: NaiveDate, hour: u32, minute: u32) -> NaiveDateTime {
+        date.and_time(NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+    }
+
+    fn config_starting_at(times: &[(u32, u32)]) -> Config {
+        Config {
+            schedule: times
+                .iter()
+                .map(|&(hour, minute)| ScheduleEntry {
+                    start: ScheduleStart::Time(NaiveTime::from_hms_opt(hour, minute, 0).unwrap()),
+                    target: ScheduleTarget::Tag("any".to_string()),
+                })
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn the_last_entry_of_the_day_lasts_past_midnight() {
+        let config = config_starting_at(&[(7, 0), (22, 0)]);
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let yesterday = today.pred_opt().unwrap();
+
+        let ((start, index), next) = current_transition(&config, at(today, 1, 30)).unwrap();
+        assert_eq!((start, index), (at(yesterday, 22, 0), 1));
+        assert_eq!(next, at(today, 7, 0));
+    }
+
+    #[test]
+    fn an_entry_after_midnight_ends_the_one_before() {
+        let config = config_starting_at(&[(23, 0), (0, 30)]);
+        let today = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+
+        let ((start, index), next) = current_transition(&config, at(today, 23, 45)).unwrap();
+        assert_eq!((start, index), (at(today, 23, 0), 0));
+        assert_eq!(next, at(tomorrow, 0, 30));
+
+        let ((start, index), next) = current_transition(&config, at(tomorrow, 0, 30)).unwrap();
+        assert_eq!((start, index), (at(tomorrow, 0, 30), 1));
+        assert_eq!(next, at(tomorrow, 23, 0));
+    }
+
+    #[test]
+    fn there_is_no_transition_without_a_schedule() {
+        let config = config_starting_at(&[]);
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        assert!(current_transition(&config, at(today, 12, 0)).is_none());
+    }
+}
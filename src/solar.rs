@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+/// The moments of the day the schedule can follow, from the sun's position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SolarEvent {
+    /// Start of civil twilight, when the sun is 6° below the horizon
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// End of civil twilight
+    Dusk,
+}
+
+impl SolarEvent {
+    pub const ALL: [SolarEvent; 4] = [
+        SolarEvent::Dawn,
+        SolarEvent::Sunrise,
+        SolarEvent::Sunset,
+        SolarEvent::Dusk,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SolarEvent::Dawn => "dawn",
+            SolarEvent::Sunrise => "sunrise",
+            SolarEvent::Sunset => "sunset",
+            SolarEvent::Dusk => "dusk",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SolarEvent> {
+        SolarEvent::ALL
+            .into_iter()
+            .find(|event| event.name() == name)
+    }
+
+    /// The sun's altitude at the event, in degrees. Sunrise and sunset account
+    /// for refraction and the size of the sun's disc.
+    fn altitude(self) -> f64 {
+        match self {
+            SolarEvent::Dawn | SolarEvent::Dusk => -6.0,
+            SolarEvent::Sunrise | SolarEvent::Sunset => -0.833,
+        }
+    }
+
+    fn is_morning(self) -> bool {
+        matches!(self, SolarEvent::Dawn | SolarEvent::Sunrise)
+    }
+}
+
+/// The Julian date of 2000-01-01 12:00 UTC.
+const J2000: f64 = 2451545.0;
+/// The Julian date of the Unix epoch.
+const UNIX_EPOCH_JULIAN_DATE: f64 = 2440587.5;
+/// The tilt of the earth's axis, in degrees.
+const OBLIQUITY: f64 = 23.4397;
+
+/// When `event` happens on `date` at the given place, following the sunrise
+/// equation. Accurate to a few minutes, which is plenty to switch wallpapers.
+/// `None` if it doesn't happen that day, like sunset during a polar day.
+pub fn event_time(
+    event: SolarEvent,
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+) -> Option<DateTime<Utc>> {
+    let j2000_date = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let days = (date - j2000_date).num_days() as f64;
+
+    // Mean solar noon, with longitudes east of Greenwich being positive
+    let mean_noon = days - longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let equation_of_center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + equation_of_center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = J2000 + mean_noon + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = (event.altitude().to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    let julian_date = if event.is_morning() {
+        transit - hour_angle / 360.0
+    } else {
+        transit + hour_angle / 360.0
+    };
+    let unix_seconds = (julian_date - UNIX_EPOCH_JULIAN_DATE) * 86400.0;
+    DateTime::from_timestamp(unix_seconds.round() as i64, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Paris
+    const LATITUDE: f64 = 48.8566;
+    const LONGITUDE: f64 = 2.3522;
+
+    fn assert_close(actual: Option<DateTime<Utc>>, expected: DateTime<Utc>) {
+        let actual = actual.expect("the event happens that day");
+        let difference = (actual - expected).num_minutes().abs();
+        assert!(difference <= 3, "{} is not close to {}", actual, expected);
+    }
+
+    #[test]
+    fn event_time_matches_the_almanac() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let utc = |hour, minute| Utc.with_ymd_and_hms(2024, 6, 21, hour, minute, 0).unwrap();
+        assert_close(
+            event_time(SolarEvent::Sunrise, date, LATITUDE, LONGITUDE),
+            utc(3, 47),
+        );
+        assert_close(
+            event_time(SolarEvent::Sunset, date, LATITUDE, LONGITUDE),
+            utc(19, 58),
+        );
+        assert_close(
+            event_time(SolarEvent::Dawn, date, LATITUDE, LONGITUDE),
+            utc(3, 4),
+        );
+        assert_close(
+            event_time(SolarEvent::Dusk, date, LATITUDE, LONGITUDE),
+            utc(20, 41),
+        );
+    }
+
+    #[test]
+    fn the_sun_does_not_set_during_a_polar_day() {
+        // Tromsø, at midsummer
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert_eq!(event_time(SolarEvent::Sunset, date, 69.6492, 18.9553), None);
+        assert_eq!(
+            event_time(SolarEvent::Sunrise, date, 69.6492, 18.9553),
+            None
+        );
+    }
+}
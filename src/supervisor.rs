@@ -0,0 +1,355 @@
+use std::ffi::OsString;
+use std::fs::{read, read_to_string, remove_file, write, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
+
+use crate::config::Config;
+use crate::library::Library;
+use crate::mpv;
+use crate::renderer;
+use crate::runner::Runner;
+
+/// How often the renderer and the signals are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait for the renderer each time it starts.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+/// The wait before the first restart after a crash. It doubles with each crash
+/// in a row, up to `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A renderer running for this long before crashing is restarted after
+/// `MIN_BACKOFF` again.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+/// How long the renderer gets to exit after SIGTERM, before it's killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the daemon writes its process id, so commands showing a video can ask
+/// it to start the renderer again. The daemon holds a lock on it while it runs.
+fn pid_file(config: &Config) -> PathBuf {
+    let mut path = OsString::from(&config.socket_path);
+    path.push(".daemon.pid");
+    PathBuf::from(path)
+}
+
+/// Left by `stopping_on_purpose`, so the daemon waits for the next video instead
+/// of restarting the renderer.
+fn stop_marker(config: &Config) -> PathBuf {
+    let mut path = OsString::from(&config.socket_path);
+    path.push(".stopped");
+    PathBuf::from(path)
+}
+
+/// Tells the daemon that the renderer is about to exit on purpose, like X11's
+/// when a still image is shown.
+pub fn stopping_on_purpose(config: &Config) -> io::Result<()> {
+    write(stop_marker(config), "")
+}
+
+/// Undoes `stopping_on_purpose`, when the renderer couldn't be stopped after all.
+pub fn not_stopping(config: &Config) {
+    let _ = remove_file(stop_marker(config));
+}
+
+/// The video the renderer's player was last asked to play, which it's started
+/// with by the daemon.
+fn video_file(config: &Config) -> PathBuf {
+    let mut path = OsString::from(&config.socket_path);
+    path.push(".video");
+    PathBuf::from(path)
+}
+
+/// Tells the daemon which video to start the player with, the next time it does.
+pub fn remember_video(config: &Config, path: &Path) {
+    if let Err(e) = write(video_file(config), path.as_os_str().as_bytes()) {
+        warn!("Could not remember the video to play: {}", e);
+    }
+}
+
+fn remembered_video(config: &Config) -> Option<PathBuf> {
+    read(video_file(config))
+        .ok()
+        .map(|bytes| PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Takes the lock a running daemon holds on `file`. Fails with `WouldBlock` if
+/// another process holds it.
+fn lock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    // SAFETY: flock only reads the descriptor, which `file` keeps open for the
+    // duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Asks the running daemon to start the renderer or its player again, after it
+/// stopped on purpose. Returns `false` if there is no daemon.
+pub fn wake(config: &Config) -> bool {
+    let Ok(file) = File::open(pid_file(config)) else {
+        return false;
+    };
+    // A pid file nobody holds a lock on was left by a daemon that was killed,
+    // and its process id may belong to another process by now
+    match lock(&file, libc::LOCK_SH) {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+        _ => return false,
+    }
+    let Some(pid) = read_to_string(pid_file(config))
+        .ok()
+        .and_then(|contents| contents.trim().parse::<libc::pid_t>().ok())
+    else {
+        return false;
+    };
+    // SAFETY: kill takes no pointers. The pid is the daemon's, which holds the lock.
+    unsafe { libc::kill(pid, SIGUSR1) == 0 }
+}
+
+/// Sends `signal` to the renderer's process group, which includes mpv.
+fn signal_renderer(renderer: &Child, signal: libc::c_int) {
+    // SAFETY: kill takes no pointers. The renderer is our child and leads its own
+    // process group, which can't be reused until it's waited for.
+    unsafe {
+        libc::kill(-(renderer.id() as libc::pid_t), signal);
+    }
+}
+
+/// Stops the renderer, killing it if it doesn't exit within `SHUTDOWN_TIMEOUT`.
+fn stop(mut renderer: Child) {
+    signal_renderer(&renderer, SIGTERM);
+    let started = Instant::now();
+    while started.elapsed() < SHUTDOWN_TIMEOUT {
+        if !matches!(renderer.try_wait(), Ok(None)) {
+            return;
+        }
+        sleep(POLL_INTERVAL);
+    }
+    signal_renderer(&renderer, libc::SIGKILL);
+    let _ = renderer.wait();
+}
+
+/// Sleeps for `duration`, or until one of the flags is raised.
+fn sleep_unless(flags: &[&AtomicBool], duration: Duration) {
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        if flags.iter().any(|flag| flag.load(Ordering::SeqCst)) {
+            return;
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// Plays the last wallpaper again, after the renderer crashed and was restarted.
+fn reload_last_wallpaper(config: &Config) {
+    let library = match Library::scan(config) {
+        Ok(library) => library,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+    if let Some(wallpaper) = library.last_played(config) {
+        info!("Reloading \"{}\"", wallpaper.id);
+        if let Err(e) = renderer::reload(config, wallpaper) {
+            error!("Failed to reload the wallpaper: {}", e);
+        }
+    }
+}
+
+/// A process the daemon keeps running.
+#[derive(Clone, Copy, PartialEq)]
+enum Process {
+    /// The renderer's daemon, started right away
+    Renderer,
+    /// The renderer's video player, when it has one, started with the first video
+    Player,
+}
+
+impl Process {
+    fn name(self) -> &'static str {
+        match self {
+            Process::Renderer => "renderer",
+            Process::Player => "video player",
+        }
+    }
+
+    /// Whether it plays videos, and so is stopped on purpose when a still image
+    /// is shown.
+    fn plays_videos(self, config: &Config) -> bool {
+        self == Process::Player || !renderer::has_player(config)
+    }
+
+    /// Starts it, or returns `None` if there is nothing for it to do yet.
+    fn spawn(self, runner: &Runner, config: &Config) -> io::Result<Option<Child>> {
+        match self {
+            Process::Renderer => renderer::spawn(runner, config),
+            Process::Player => match remembered_video(config) {
+                Some(path) => renderer::spawn_player(runner, config, &path),
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn wait_until_ready(self, runner: &Runner, config: &Config) -> io::Result<()> {
+        match self {
+            Process::Renderer => renderer::wait_until_ready(runner, config, READY_TIMEOUT),
+            Process::Player => mpv::wait_for_socket(&config.socket_path, READY_TIMEOUT),
+        }
+    }
+
+    /// Plays the last wallpaper again after a crash. The player is started
+    /// with it already.
+    fn reload(self, config: &Config) {
+        if self == Process::Renderer {
+            reload_last_wallpaper(config);
+        }
+    }
+}
+
+/// Waits for SIGUSR1. Returns `false` if SIGTERM or SIGINT came first.
+fn wait_for_wake_up(terminate: &AtomicBool, wake_up: &AtomicBool) -> bool {
+    while !wake_up.load(Ordering::SeqCst) {
+        if terminate.load(Ordering::SeqCst) {
+            return false;
+        }
+        sleep(POLL_INTERVAL);
+    }
+    true
+}
+
+/// Runs the renderer, and its video player if it has one, as child processes
+/// until SIGTERM or SIGINT, restarting them with an increasing delay whenever
+/// they exit. The one playing videos is stopped on purpose when a still image is
+/// shown, and started again on SIGUSR1 instead.
+pub fn run(config: &Config) -> anyhow::Result<()> {
+    let terminate = Arc::new(AtomicBool::new(false));
+    let wake_up = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, Arc::clone(&terminate))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&terminate))?;
+    signal_hook::flag::register(SIGUSR1, Arc::clone(&wake_up))?;
+
+    let pid_file = pid_file(config);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&pid_file)?;
+    if let Err(e) = lock(&file, libc::LOCK_EX) {
+        anyhow::bail!(
+            "Could not lock {}, is another daemon running? {}",
+            pid_file.to_string_lossy(),
+            e
+        );
+    }
+    file.set_len(0)?;
+    file.write_all(std::process::id().to_string().as_bytes())?;
+    // Left by an earlier daemon. The player waits for the next video.
+    let _ = remove_file(video_file(config));
+
+    let result = if renderer::has_player(config) {
+        // Only the player is woken up
+        let never = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let player = scope.spawn(|| {
+                let result = supervise(config, Process::Player, &terminate, &wake_up);
+                terminate.store(true, Ordering::SeqCst);
+                result
+            });
+            let result = supervise(config, Process::Renderer, &terminate, &never);
+            terminate.store(true, Ordering::SeqCst);
+            let player_result = player
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("The video player's supervisor panicked")));
+            result.and(player_result)
+        })
+    } else {
+        supervise(config, Process::Renderer, &terminate, &wake_up)
+    };
+    let _ = remove_file(video_file(config));
+    let _ = remove_file(&pid_file);
+    result
+}
+
+fn supervise(
+    config: &Config,
+    process: Process,
+    terminate: &AtomicBool,
+    wake_up: &AtomicBool,
+) -> anyhow::Result<()> {
+    let runner = Runner::default();
+    let mut backoff = MIN_BACKOFF;
+    let mut crashed = false;
+
+    loop {
+        let started = Instant::now();
+        // Requests sent before now are answered by this start
+        wake_up.store(false, Ordering::SeqCst);
+        let plays_videos = process.plays_videos(config);
+        if plays_videos {
+            not_stopping(config);
+        }
+        let Some(mut child) = process.spawn(&runner, config)? else {
+            if !wait_for_wake_up(terminate, wake_up) {
+                return Ok(());
+            }
+            continue;
+        };
+        match process.wait_until_ready(&runner, config) {
+            Ok(()) if crashed => process.reload(config),
+            Ok(()) => {}
+            Err(e) => warn!("The {} did not become ready: {}", process.name(), e),
+        }
+
+        let status = loop {
+            if terminate.load(Ordering::SeqCst) {
+                stop(child);
+                return Ok(());
+            }
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            sleep(POLL_INTERVAL);
+        };
+
+        if plays_videos && stop_marker(config).exists() {
+            not_stopping(config);
+            info!(
+                "The {} stopped. It starts again with the next video.",
+                process.name()
+            );
+            if !wait_for_wake_up(terminate, wake_up) {
+                return Ok(());
+            }
+            backoff = MIN_BACKOFF;
+            crashed = false;
+            continue;
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            backoff = MIN_BACKOFF;
+        }
+        warn!(
+            "The {} exited with {}. Restarting it in {} seconds.",
+            process.name(),
+            status,
+            backoff.as_secs()
+        );
+        // A video shown in the meantime doesn't wait for the end of the delay
+        sleep_unless(&[terminate, wake_up], backoff);
+        if terminate.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        crashed = true;
+    }
+}
@@ -1,28 +1,87 @@
 use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    process::Command,
 };
 
-fn ask_user_input(path: &Path) -> Result<String, String> {
-    Command::new("sxiv")
-        .args(["-t", "-o", &path.to_string_lossy()])
-        .output()
-        .map_err(|_| "Failed to execute sxiv".to_string())
-        .and_then(|val| {
-            String::from_utf8(val.stdout).map_err(|_| "sxiv output is not valid utf8".to_string())
-        })
+use crate::config::Program;
+use crate::runner::Runner;
+use crate::utils::make_error_message_after_command_call;
+
+fn ask_user_input(runner: &Runner, sxiv: &Program, path: &Path) -> Result<Vec<u8>, String> {
+    runner
+        .output(
+            sxiv.command()
+                .args(["-t", "-o", "-r"])
+                .args(&sxiv.args)
+                .arg(path),
+        )
+        .map(|val| val.stdout)
+        .map_err(|err| make_error_message_after_command_call(&sxiv.name(), err))
 }
 
-pub fn ask_user_input_single(path: &Path) -> Result<PathBuf, String> {
-    return ask_user_input(path)?
-        .split('\n')
+/// Splits sxiv's output into the selected paths. sxiv prints one path per line,
+/// so a file name containing a newline spans multiple lines. Lines are joined back
+/// together until they form a path that exists.
+fn parse_selected_paths(output: &[u8]) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    let mut pending: Vec<u8> = vec![];
+    for line in output.split(|&c| c == b'\n') {
+        if !pending.is_empty() {
+            pending.push(b'\n');
+        }
+        pending.extend_from_slice(line);
+
+        let path = Path::new(OsStr::from_bytes(&pending));
+        if path.exists() {
+            paths.push(path.to_path_buf());
+            pending.clear();
+        }
+    }
+    paths
+}
+
+pub fn ask_user_input_single(
+    runner: &Runner,
+    sxiv: &Program,
+    path: &Path,
+) -> Result<PathBuf, String> {
+    parse_selected_paths(&ask_user_input(runner, sxiv, path)?)
+        .into_iter()
         .next()
-        .and_then(|val| {
-            if val.is_empty() {
-                None
-            } else {
-                Some(PathBuf::from(val))
-            }
-        })
-        .ok_or_else(|| "No wallpaper selected.".to_string());
+        .ok_or_else(|| "No wallpaper selected.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use super::*;
+
+    #[test]
+    fn parse_selected_paths_joins_names_with_newlines() {
+        let dir =
+            std::env::temp_dir().join(format!("wallpaper-manager-sxiv-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let names: [&[u8]; 4] = [
+            b"plain.mp4",
+            b"two\nlines.mp4",
+            "forêt \"1\".mp4".as_bytes(),
+            b"a\x80.mp4",
+        ];
+        let paths: Vec<PathBuf> = names
+            .iter()
+            .map(|name| dir.join(OsStr::from_bytes(name)))
+            .collect();
+        let mut output = vec![];
+        for path in &paths {
+            write(path, "").unwrap();
+            output.extend_from_slice(path.as_os_str().as_bytes());
+            output.push(b'\n');
+        }
+
+        let parsed = parse_selected_paths(&output);
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(parsed, paths);
+    }
 }
@@ -1,4 +1,8 @@
+use std::ffi::OsString;
+use std::fs::read_dir;
 use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 
 pub fn make_error_message_after_command_call(command_name: &str, err: Error) -> String {
     match err.kind() {
@@ -22,3 +26,148 @@ pub fn trim_string(s: &mut String) -> &mut String {
 
     s
 }
+
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, SIZE_UNITS[0])
+    } else {
+        format!("{:.1} {}", size, SIZE_UNITS[unit])
+    }
+}
+
+/// Parses sizes such as `500M`, `2.5GiB` or `1024`. Units are powers of 1024,
+/// with or without the `iB`/`B` suffix.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split_at = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split_at);
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Lists every regular file inside `dir` and its subdirectories, sorted.
+pub fn walk_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut pending_dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = pending_dirs.pop() {
+        for entry in read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending_dirs.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Turns a path into a string that can be stored in text formats like TOML. Bytes
+/// that are not valid UTF-8 are written as `\xNN`, and backslashes as `\\`, so
+/// distinct paths keep distinct keys.
+pub fn path_to_key(path: &Path) -> String {
+    let mut key = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        key.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            key.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    key
+}
+
+/// Reads back a key made by `path_to_key`.
+pub fn key_to_path(key: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut rest = key.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        if let Some((b'\\', tail)) = rest.split_first() {
+            bytes.push(b'\\');
+            rest = tail;
+            continue;
+        }
+        let escaped = rest
+            .strip_prefix(b"x")
+            .and_then(|tail| tail.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &rest[3..];
+            }
+            None => bytes.push(byte),
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn key(bytes: &[u8]) -> String {
+        path_to_key(Path::new(OsStr::from_bytes(bytes)))
+    }
+
+    #[test]
+    fn path_to_key_keeps_valid_utf8() {
+        assert_eq!(key(b"forest/rain.mp4"), "forest/rain.mp4");
+        assert_eq!(key("for\"êt \"2\".mp4".as_bytes()), "for\"êt \"2\".mp4");
+        assert_eq!(key(b"two\nlines.mp4"), "two\nlines.mp4");
+    }
+
+    #[test]
+    fn path_to_key_escapes_invalid_bytes_and_backslashes() {
+        assert_eq!(key(b"a\x80b.mp4"), "a\\x80b.mp4");
+        assert_eq!(key(b"a\\b.mp4"), "a\\\\b.mp4");
+    }
+
+    #[test]
+    fn path_to_key_keeps_distinct_paths_distinct() {
+        assert_ne!(key(b"a\\x80"), key(b"a\x80"));
+        assert_ne!(key(b"a\\\\"), key(b"a\\"));
+    }
+
+    #[test]
+    fn key_to_path_reverses_path_to_key() {
+        for name in [
+            &b"forest/rain.mp4"[..],
+            "forêt \"2\".mp4".as_bytes(),
+            b"two\nlines.mp4",
+            b"a\x80b\xff.mp4",
+            b"a\\x80",
+            b"a\\\\",
+        ] {
+            let path = Path::new(OsStr::from_bytes(name));
+            assert_eq!(key_to_path(&path_to_key(path)), path);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+/// Files are only reported once nothing happened to them for this long, so that
+/// large copies aren't read half-written.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const EVENTS_BUFFER_SIZE: usize = 4096;
+
+/// What happened in the watched directory, relative to it.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Files created, modified or moved in.
+    pub changed: Vec<PathBuf>,
+    /// Files and folders deleted or moved away.
+    pub removed: Vec<PathBuf>,
+    /// Some events were lost, so anything may have changed.
+    pub overflowed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Changed,
+    Removed,
+}
+
+/// Watches a directory and the folders below it.
+pub struct Watcher {
+    inotify: Inotify,
+    dir: PathBuf,
+    /// The folder of each watch, relative to `dir`.
+    folders: HashMap<WatchDescriptor, PathBuf>,
+    /// Changes not settled yet, with when they last happened.
+    pending: HashMap<PathBuf, (Change, Instant)>,
+    overflowed_at: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn new(dir: &Path) -> io::Result<Watcher> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            dir: dir.to_path_buf(),
+            folders: HashMap::new(),
+            pending: HashMap::new(),
+            overflowed_at: None,
+        };
+        watcher.watch_tree(Path::new(""), false)?;
+        Ok(watcher)
+    }
+
+    /// Watches a folder and the ones below it. Its files are reported as changed
+    /// when `new`, as they may have been written before the watch was added.
+    fn watch_tree(&mut self, folder: &Path, new: bool) -> io::Result<()> {
+        let mask = WatchMask::CREATE
+            | WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        let path = self.dir.join(folder);
+        let wd = self.inotify.watches().add(&path, mask)?;
+        self.folders.insert(wd, folder.to_path_buf());
+
+        for entry in read_dir(&path)?.flatten() {
+            let relative = folder.join(entry.file_name());
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    // Removed in the meantime. Its own event says so.
+                    let _ = self.watch_tree(&relative, new);
+                }
+                Ok(_) if new => self.mark(relative, Change::Changed),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops watching a folder moved away, and the ones below it.
+    fn unwatch_tree(&mut self, folder: &Path) {
+        let watches: Vec<WatchDescriptor> = self
+            .folders
+            .iter()
+            .filter(|(_, path)| path.starts_with(folder))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in watches {
+            self.folders.remove(&wd);
+            let _ = self.inotify.watches().remove(wd);
+        }
+    }
+
+    fn mark(&mut self, path: PathBuf, change: Change) {
+        self.pending.insert(path, (change, Instant::now()));
+    }
+
+    /// Reads the events that happened since the last call.
+    fn read_events(&mut self) -> io::Result<()> {
+        let mut buffer = [0; EVENTS_BUFFER_SIZE];
+        loop {
+            let events: Vec<_> = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events.map(|event| event.to_owned()).collect(),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    self.overflowed_at = Some(Instant::now());
+                    continue;
+                }
+                if event.mask.contains(EventMask::IGNORED) {
+                    self.folders.remove(&event.wd);
+                    continue;
+                }
+                let (Some(folder), Some(name)) = (self.folders.get(&event.wd), &event.name) else {
+                    continue;
+                };
+                let path = folder.join(name);
+                let removed = event
+                    .mask
+                    .intersects(EventMask::DELETE | EventMask::MOVED_FROM);
+                if !event.mask.contains(EventMask::ISDIR) {
+                    self.mark(
+                        path,
+                        if removed {
+                            Change::Removed
+                        } else {
+                            Change::Changed
+                        },
+                    );
+                } else if removed {
+                    self.unwatch_tree(&path);
+                    self.mark(path, Change::Removed);
+                } else if event
+                    .mask
+                    .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    // Removed in the meantime. Its own event says so.
+                    let _ = self.watch_tree(&path, true);
+                }
+            }
+        }
+    }
+
+    /// Waits for changes, and returns them once they have settled.
+    pub fn next(&mut self) -> io::Result<Changes> {
+        loop {
+            self.read_events()?;
+            let now = Instant::now();
+            let settled = |at: Instant| now.duration_since(at) >= SETTLE_TIME;
+
+            let mut changes = Changes {
+                overflowed: self.overflowed_at.is_some_and(settled),
+                ..Changes::default()
+            };
+            if changes.overflowed {
+                self.overflowed_at = None;
+            }
+            self.pending.retain(|path, (change, at)| {
+                if !settled(*at) {
+                    return true;
+                }
+                match change {
+                    Change::Changed => changes.changed.push(path.clone()),
+                    Change::Removed => changes.removed.push(path.clone()),
+                }
+                false
+            });
+            if changes.overflowed || !changes.changed.is_empty() || !changes.removed.is_empty() {
+                changes.changed.sort();
+                changes.removed.sort();
+                return Ok(changes);
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+}